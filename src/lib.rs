@@ -0,0 +1,6029 @@
+//! Download GitHub issue and pull request attachments using GitHub CLI authentication.
+//!
+//! [`AssetDownloader`] does the work behind the `gh-asset` binary and can be embedded in other
+//! tools. Build a [`Config`], then call [`AssetDownloader::download`]:
+//!
+//! ```no_run
+//! # async fn run() -> gh_asset::Result<()> {
+//! use gh_asset::{AssetDownloader, Config};
+//!
+//! let downloader = AssetDownloader::new(Config::default())?;
+//! if let Some(outcome) = downloader.download("1234abcd-1234-1234-1234-1234abcd1234", "./downloads").await? {
+//!     println!("saved {} bytes to {}", outcome.bytes_written, outcome.path.display());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Failures are returned as a [`DownloadError`], so callers can tell e.g. an
+//! [`HttpStatus`](DownloadError::HttpStatus) of 404 apart from a network timeout.
+
+use clap::ValueEnum;
+use futures_util::StreamExt;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use regex::Regex;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Why a download, listing or token lookup failed. The messages are written for users; the
+/// variants let callers tell, say, a missing asset apart from a network timeout.
+#[derive(Debug, thiserror::Error)]
+pub enum DownloadError {
+    /// No token in the environment, and none could be read from the GitHub CLI.
+    #[error("{0}")]
+    AuthFailed(String),
+    /// The asset ID, or the text it was to be found in, isn't usable.
+    #[error("{0}")]
+    InvalidAssetId(String),
+    /// The destination is a system directory, outside the allowed root, or not a valid filename.
+    #[error("{0}")]
+    PathRejected(String),
+    /// The file is already there and neither `overwrite` nor `no_clobber` is set.
+    #[error("{} already exists. Pass --overwrite to replace it or --no-clobber to skip it", .0.display())]
+    AlreadyExists(PathBuf),
+    /// GitHub answered with an error status, after any retries.
+    #[error("{message}")]
+    HttpStatus { status: u16, message: String },
+    /// The request couldn't be sent or the body couldn't be read, including timeouts.
+    #[error("{context}: {error}")]
+    Network { context: String, error: reqwest::Error },
+    #[error("{context}: {error}")]
+    Io { context: String, error: std::io::Error },
+    /// The response can't be used: unparseable JSON, a bad redirect, or an HTML page in place
+    /// of the asset.
+    #[error("{0}")]
+    InvalidResponse(String),
+    /// The archive can't be read, or has an entry that would land outside the target directory.
+    #[error("{0}")]
+    Archive(String),
+    /// The `gh` transport failed to run or reported an error.
+    #[error("{0}")]
+    GhCli(String),
+    /// The downloaded bytes don't have the digest given with `sha256`. Nothing was saved.
+    #[error("SHA-256 mismatch: expected {expected}, got {actual}")]
+    ChecksumMismatch { expected: String, actual: String },
+    /// The asset is bigger than `max_size`. The partial file was deleted.
+    #[error("Asset is larger than the {limit}-byte limit set with --max-size")]
+    TooLarge { limit: u64 },
+    /// `convert` was set but the asset isn't an image that can be re-encoded.
+    #[error("{0}")]
+    Convert(String),
+    /// Some assets of a batch failed; each failure was reported as it happened.
+    #[error("{failed} of {total} downloads failed")]
+    BatchFailed { failed: usize, total: usize },
+}
+
+impl DownloadError {
+    /// A stable name for the variant, used in `--json` error output.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DownloadError::AuthFailed(_) => "auth_failed",
+            DownloadError::InvalidAssetId(_) => "invalid_asset_id",
+            DownloadError::PathRejected(_) => "path_rejected",
+            DownloadError::AlreadyExists(_) => "already_exists",
+            DownloadError::HttpStatus { .. } => "http_status",
+            DownloadError::Network { .. } => "network",
+            DownloadError::Io { .. } => "io",
+            DownloadError::InvalidResponse(_) => "invalid_response",
+            DownloadError::Archive(_) => "archive",
+            DownloadError::GhCli(_) => "gh_cli",
+            DownloadError::ChecksumMismatch { .. } => "checksum_mismatch",
+            DownloadError::TooLarge { .. } => "too_large",
+            DownloadError::Convert(_) => "convert",
+            DownloadError::BatchFailed { .. } => "batch_failed",
+        }
+    }
+
+    /// The process exit status the CLI reports this error with, so scripts can branch on the
+    /// kind of failure: 2 for unusable input, 3 for authentication (including 401 and 403
+    /// responses), 4 for a missing asset (404 or 410), 5 for network failures and timeouts,
+    /// and 1 for anything else.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            DownloadError::InvalidAssetId(_) | DownloadError::PathRejected(_) => 2,
+            DownloadError::AuthFailed(_) | DownloadError::HttpStatus { status: 401 | 403, .. } => 3,
+            DownloadError::HttpStatus { status: 404 | 410, .. } => 4,
+            DownloadError::Network { .. } => 5,
+            _ => 1,
+        }
+    }
+
+    /// The error as a JSON object with its kind and message, plus the status code for
+    /// [`DownloadError::HttpStatus`].
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut object = serde_json::json!({
+            "error": self.kind(),
+            "message": self.to_string(),
+        });
+        if let DownloadError::HttpStatus { status, .. } = self {
+            object["status"] = (*status).into();
+        }
+        object
+    }
+
+    fn io(context: impl Into<String>, error: std::io::Error) -> Self {
+        DownloadError::Io { context: context.into(), error }
+    }
+
+    fn network(context: impl Into<String>, error: reqwest::Error) -> Self {
+        DownloadError::Network { context: context.into(), error }
+    }
+}
+
+pub type Result<T, E = DownloadError> = std::result::Result<T, E>;
+
+/// How an asset is fetched.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Transport {
+    /// Talk to GitHub directly
+    #[default]
+    Reqwest,
+    /// Stream it through `gh api`, using gh's own networking and auth
+    Gh,
+}
+
+/// Download settings. Each field corresponds to the `gh-asset download` flag of the same name.
+#[derive(Default)]
+pub struct Config {
+    /// GitHub Enterprise Server hostname, e.g. `github.mycorp.com`. None means github.com
+    pub host: Option<String>,
+    pub transport: Transport,
+    /// Extensions or MIME types (`mp4`, `video/*`) an asset must match to be downloaded
+    pub only_if_type: Vec<String>,
+    pub verify_type: bool,
+    /// Asset ID to MIME type, taking precedence over what the server reports
+    pub content_type_overrides: HashMap<String, String>,
+    pub verbose: bool,
+    pub replace_existing_only: bool,
+    /// None picks the default: `error` for binary target extensions, `save` otherwise
+    pub on_html: Option<OnHtml>,
+    /// Fail instead of warning when an explicit filename's extension disagrees with the asset's type
+    pub strict: bool,
+    pub hash_name: bool,
+    pub hash_name_length: usize,
+    /// Names directory downloads by this template instead of `<asset-id><ext>`
+    pub rename: Option<RenameTemplate>,
+    /// Seconds allowed for each GET, body transfer included. None and 0 both mean no overall
+    /// limit, leaving stalls to `stall_timeout`
+    pub timeout: Option<u64>,
+    /// Seconds to wait for the next bytes of a response before giving up on it. The window
+    /// restarts with every chunk received, so a download that keeps progressing never hits it.
+    /// None means the 300s default and 0 no stall limit at all
+    pub stall_timeout: Option<u64>,
+    /// Seconds allowed to establish each connection (DNS, TCP and TLS), independent of
+    /// `timeout`. None leaves it to the overall timeout
+    pub connect_timeout: Option<u64>,
+    /// Proxy for every request, in place of `HTTPS_PROXY`/`HTTP_PROXY`/`ALL_PROXY`. Hosts in
+    /// `NO_PROXY` still bypass it
+    pub proxy: Option<String>,
+    /// User-Agent to send instead of `gh-asset/<version>`
+    pub user_agent: Option<String>,
+    /// Caps each download's body transfer at this many bytes per second
+    pub limit_rate: Option<u64>,
+    pub timeout_retries: u32,
+    pub retries: u32,
+    pub preserve_query_filename: bool,
+    pub use_original_name: bool,
+    /// Directory to unpack archives into; validate it with
+    /// [`AssetDownloader::validate_destination_path`] first
+    pub extract_dir: Option<PathBuf>,
+    pub keep_archive: bool,
+    pub prompt_on_overwrite: bool,
+    pub overwrite: bool,
+    pub no_clobber: bool,
+    /// Create missing parent directories of the destination. Without it a missing parent is an
+    /// error, so a mistyped path doesn't quietly become a new directory tree
+    pub create_dirs: bool,
+    pub trace_file: Option<TraceFile>,
+    pub root: Option<PathBuf>,
+    pub allow_outside_cwd: bool,
+    pub lenient_id: bool,
+    /// Skip the asset ID format check, only refusing IDs that could change the URL's path, for
+    /// when GitHub introduces a new format
+    pub no_verify_asset_id: bool,
+    /// Accept numeric release asset IDs of this repository and download them from the releases
+    /// API instead of user-attachments
+    pub release_repo: Option<RepoRef>,
+    /// The repository the asset was posted in, as given with `--repo`. Recorded in the outcome
+    /// and the metadata sidecar; the asset ID alone is still what gets downloaded
+    pub repo: Option<RepoRef>,
+    pub write_metadata_sidecar: bool,
+    /// For an explicit filename whose metadata sidecar recorded an ETag or Last-Modified, asks
+    /// for the asset only if it changed since, and skips it on `304 Not Modified`
+    pub if_modified_since: bool,
+    /// Save a zero-byte body instead of failing with "downloaded asset was empty"
+    pub allow_empty: bool,
+    pub quiet: bool,
+    /// How many assets `download_many` fetches at once; 0 is treated as 1
+    pub concurrency: usize,
+    pub resume: bool,
+    /// Resolve the final path, probing the type if needed, but don't download anything
+    pub dry_run: bool,
+    /// Accept header to send when fetching the asset. None sends none, except to the releases
+    /// API, which needs `application/octet-stream` to return the bytes
+    pub accept: Option<String>,
+    /// Extra headers for every request to the asset's own host, e.g. for a proxy in front of
+    /// GitHub Enterprise. Authorization is never taken from here
+    pub headers: Vec<(String, String)>,
+    /// Re-encode downloaded images into this format, replacing the original file unless
+    /// `keep_original` is set. Other assets fail with [`DownloadError::Convert`]
+    pub convert: Option<ConvertFormat>,
+    pub keep_original: bool,
+    /// Replace a download whose content matches an earlier one of this run in the same
+    /// directory with a hard link to it (a symlink where hard links aren't possible)
+    pub dedupe: bool,
+    /// In directory mode, save as `name (1).ext`, `name (2).ext`, ... instead of replacing or
+    /// refusing an existing file. The free name is picked when the download is moved into place
+    pub unique_names: bool,
+    /// Expected SHA-256 of the downloaded bytes, as lowercase hex
+    pub sha256: Option<String>,
+    pub print_sha256: bool,
+    /// Refuse assets larger than this many bytes
+    pub max_size: Option<u64>,
+    /// Print one JSON object per finished download to stdout, and failures as JSON to stderr,
+    /// instead of the usual progress messages
+    pub json: bool,
+    /// Set when the destination is `-`: the asset goes to stdout and messages to stderr. Also
+    /// worth setting for a pipe or device destination, which may be stdout too
+    pub stdout: bool,
+}
+
+/// Where a fetched body came from, as reported by the transport.
+struct AssetSource {
+    asset_id: String,
+    source_url: String,
+    final_url: Option<String>,
+    content_type: Option<String>,
+}
+
+/// What [`AssetDownloader::download`] saved.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DownloadOutcome {
+    /// Where the asset was saved: the file, `-` for stdout, or the directory it was extracted
+    /// into. Under `dry_run`, where it would be saved
+    pub path: PathBuf,
+    /// Bytes of the asset received, including any resumed ones; 0 under `dry_run`
+    pub bytes_written: u64,
+    /// The Content-Type the server sent
+    pub content_type: Option<String>,
+    /// The extension of the saved file, dot included
+    pub extension: Option<String>,
+    /// The repository from [`Config::repo`]
+    pub repo: Option<RepoRef>,
+}
+
+impl DownloadOutcome {
+    fn new(path: PathBuf, bytes_written: u64, content_type: Option<String>) -> Self {
+        let extension = path.extension().map(|ext| format!(".{}", ext.to_string_lossy()));
+        DownloadOutcome { path, bytes_written, content_type, extension, repo: None }
+    }
+}
+
+/// Provenance of a finished download, serialized into the metadata sidecar.
+#[derive(Serialize)]
+struct DownloadStats {
+    asset_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    repo: Option<String>,
+    source_url: String,
+    final_url: Option<String>,
+    content_type: Option<String>,
+    path: PathBuf,
+    size: u64,
+    sha256: String,
+    downloaded_at: String,
+    #[serde(flatten)]
+    validators: Validators,
+}
+
+/// Cache validators of a downloaded asset, kept in the metadata sidecar for `--if-modified-since`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, serde::Deserialize)]
+struct Validators {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    etag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_modified: Option<String>,
+}
+
+impl Validators {
+    fn from_headers(etag: Option<&str>, last_modified: Option<&str>) -> Self {
+        Validators { etag: etag.map(str::to_string), last_modified: last_modified.map(str::to_string) }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+/// The parts of an earlier metadata sidecar that decide whether it still describes the file.
+#[derive(serde::Deserialize)]
+struct RecordedDownload {
+    asset_id: String,
+    size: u64,
+    #[serde(flatten)]
+    validators: Validators,
+}
+
+impl DownloadStats {
+    fn new(source: AssetSource, repo: Option<&RepoRef>, path: &Path, size: u64, sha256: String) -> Self {
+        DownloadStats {
+            asset_id: source.asset_id,
+            repo: repo.map(RepoRef::to_string),
+            source_url: source.source_url,
+            final_url: source.final_url,
+            content_type: source.content_type,
+            path: path.to_path_buf(),
+            size,
+            sha256,
+            downloaded_at: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            validators: Validators::default(),
+        }
+    }
+}
+
+/// Appends one JSON object per line to `--trace-file` describing each request, response and
+/// retry decision, regardless of console verbosity.
+pub struct TraceFile {
+    file: std::sync::Mutex<File>,
+}
+
+impl TraceFile {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path)
+            .map_err(|e| DownloadError::io(format!("Failed to create trace file {}", path.display()), e))?;
+        Ok(TraceFile { file: std::sync::Mutex::new(file) })
+    }
+
+    fn record(&self, mut event: serde_json::Value) {
+        let timestamp_ms = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        event["timestamp_ms"] = timestamp_ms.into();
+
+        // Tracing is best-effort and must never fail the download itself
+        if let Ok(mut file) = self.file.lock() {
+            let _ = writeln!(file, "{}", event);
+        }
+    }
+}
+
+/// Query parameters that carry credentials, such as the signature of a presigned storage URL.
+const SENSITIVE_QUERY_PARAMS: [&str; 7] =
+    ["token", "access_token", "jwt", "sig", "signature", "x-amz-signature", "x-amz-security-token"];
+
+/// The URL with the values of credential-carrying query parameters replaced, for logging.
+fn redact_url(url: &reqwest::Url) -> String {
+    if url.query().is_none() {
+        return url.to_string();
+    }
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .map(|(name, value)| {
+            let sensitive = SENSITIVE_QUERY_PARAMS.iter().any(|param| name.eq_ignore_ascii_case(param));
+            let value = if sensitive { "[REDACTED]".to_string() } else { value.into_owned() };
+            (name.into_owned(), value)
+        })
+        .collect();
+    let mut redacted = url.clone();
+    redacted.query_pairs_mut().clear().extend_pairs(pairs);
+    redacted.to_string()
+}
+
+fn redact_headers(headers: &reqwest::header::HeaderMap) -> serde_json::Map<String, serde_json::Value> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if name == reqwest::header::AUTHORIZATION {
+                "[REDACTED]".to_string()
+            } else {
+                String::from_utf8_lossy(value.as_bytes()).into_owned()
+            };
+            (name.to_string(), value.into())
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+/// What to do when GitHub answers with an HTML page instead of the asset.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OnHtml {
+    Error,
+    Save,
+    Skip,
+}
+
+struct DetectedType {
+    extension: String,
+    mime_type: Option<String>,
+    source: TypeSource,
+    /// Raw header values that were looked at, kept for the `--verbose` naming trace
+    considered: Vec<(&'static str, String)>,
+    /// Original filename carried in a signed URL's `response-content-disposition` parameter
+    query_filename: Option<String>,
+    /// Filename from the response's own Content-Disposition header, when it is a safe name
+    disposition_filename: Option<String>,
+}
+
+/// Every extension candidate seen for an asset, alongside the one that was chosen.
+struct ExtensionResolution {
+    from_url: Option<String>,
+    from_disposition: Option<String>,
+    from_content_type: Option<String>,
+    chosen: DetectedType,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TypeSource {
+    Override,
+    Url,
+    Disposition,
+    ContentType,
+    Fallback,
+}
+
+impl std::fmt::Display for TypeSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            TypeSource::Override => "--content-type-override",
+            TypeSource::Url => "redirect URL",
+            TypeSource::Disposition => "content-disposition",
+            TypeSource::ContentType => "content-type",
+            TypeSource::Fallback => "fallback",
+        };
+        f.write_str(name)
+    }
+}
+
+const DEFAULT_HOST: &str = "github.com";
+
+const DEFAULT_USER_AGENT: &str = concat!("gh-asset/", env!("CARGO_PKG_VERSION"));
+
+/// Directories a download may never be written into, however the path reaches them.
+#[cfg(not(windows))]
+fn system_dirs() -> Vec<PathBuf> {
+    ["/etc", "/usr", "/var", "/sys", "/proc", "/root", "/boot"].iter().map(PathBuf::from).collect()
+}
+
+/// The usual locations, plus wherever the environment says Windows and programs are installed
+/// when that isn't on `C:`.
+#[cfg(windows)]
+fn system_dirs() -> Vec<PathBuf> {
+    let defaults = [r"C:\Windows", r"C:\Program Files", r"C:\Program Files (x86)", r"C:\ProgramData"];
+    let from_env = ["SystemRoot", "ProgramFiles", "ProgramFiles(x86)", "ProgramData"]
+        .iter()
+        .filter_map(|name| std::env::var_os(name));
+    defaults.iter().map(PathBuf::from).chain(from_env.map(PathBuf::from)).collect()
+}
+
+/// Environment variables checked for a token, in order, before falling back to the GitHub CLI.
+/// `GH_TOKEN` is what gh itself reads, and passes on to extensions.
+const TOKEN_ENV_VARS: [&str; 3] = ["GH_ASSET_TOKEN", "GH_TOKEN", "GITHUB_TOKEN"];
+
+/// An issue or pull request, written `owner/repo#number`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IssueRef {
+    pub owner: String,
+    pub repo: String,
+    pub number: u64,
+}
+
+impl IssueRef {
+    fn api_url(&self, host: &str) -> String {
+        format!("{}/repos/{}/{}/issues/{}", api_base(host), self.owner, self.repo, self.number)
+    }
+}
+
+impl std::str::FromStr for IssueRef {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected <owner>/<repo>#<number>, got '{}'", value);
+        let (repo_path, number) = value.split_once('#').ok_or_else(invalid)?;
+        let (owner, repo) = repo_path.split_once('/').ok_or_else(invalid)?;
+        let number = number.parse().map_err(|_| invalid())?;
+
+        if !is_valid_repo_name(owner) || !is_valid_repo_name(repo) {
+            return Err(invalid());
+        }
+
+        Ok(IssueRef { owner: owner.to_string(), repo: repo.to_string(), number })
+    }
+}
+
+impl std::fmt::Display for IssueRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}#{}", self.owner, self.repo, self.number)
+    }
+}
+
+/// A repository, written `owner/repo`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RepoRef {
+    pub owner: String,
+    pub repo: String,
+}
+
+impl RepoRef {
+    fn release_asset_url(&self, host: &str, asset_id: &str) -> String {
+        format!("{}/repos/{}/{}/releases/assets/{}", api_base(host), self.owner, self.repo, asset_id)
+    }
+
+    /// Tags may contain `/` and other characters that would change the path, so the tag is
+    /// percent-encoded as a single segment.
+    fn release_by_tag_url(&self, host: &str, tag: &str) -> String {
+        format!("{}/repos/{}/{}/releases/tags/{}", api_base(host), self.owner, self.repo, encode_path_segment(tag))
+    }
+}
+
+impl std::str::FromStr for RepoRef {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected <owner>/<repo>, got '{}'", value);
+        let (owner, repo) = value.split_once('/').ok_or_else(invalid)?;
+        if !is_valid_repo_name(owner) || !is_valid_repo_name(repo) {
+            return Err(invalid());
+        }
+        Ok(RepoRef { owner: owner.to_string(), repo: repo.to_string() })
+    }
+}
+
+impl std::fmt::Display for RepoRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.owner, self.repo)
+    }
+}
+
+/// Percent-encodes everything but RFC 3986's unreserved characters.
+fn encode_path_segment(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn is_valid_repo_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// A filename template for directory downloads, such as `{date}-{id}{ext}`. `{id}` is the
+/// asset ID, `{ext}` the detected extension with its dot, `{date}` today's date as
+/// `YYYY-MM-DD`, and `{original}` the uploaded filename (or `{id}{ext}` when there is none).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RenameTemplate(String);
+
+impl RenameTemplate {
+    const PLACEHOLDERS: [&'static str; 4] = ["{id}", "{ext}", "{date}", "{original}"];
+
+    fn render(&self, asset_id: &str, extension: &str, original: Option<&str>, date: &str) -> String {
+        let default_name = format!("{}{}", asset_id, extension);
+        self.0
+            .replace("{id}", asset_id)
+            .replace("{ext}", extension)
+            .replace("{date}", date)
+            .replace("{original}", original.unwrap_or(&default_name))
+    }
+}
+
+impl std::str::FromStr for RenameTemplate {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.contains(['/', '\\']) {
+            return Err(format!("'{}' would produce a path; the template can only name a file", value));
+        }
+        if value.trim().is_empty() || value == "." || value == ".." {
+            return Err(format!("'{}' is not a usable filename", value));
+        }
+        let rest = Self::PLACEHOLDERS.iter().fold(value.to_string(), |rest, placeholder| rest.replace(placeholder, ""));
+        if rest.contains(['{', '}']) {
+            return Err(format!("unknown placeholder in '{}'; use {}", value, Self::PLACEHOLDERS.join(", ")));
+        }
+        Ok(RenameTemplate(value.to_string()))
+    }
+}
+
+/// An image format for `--convert`, written as its extension, such as `png` or `jpg`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConvertFormat(image::ImageFormat);
+
+impl ConvertFormat {
+    const SUPPORTED: &'static str = "png, jpg, gif, webp, bmp, tiff";
+
+    fn extension(self) -> &'static str {
+        self.0.extensions_str()[0]
+    }
+}
+
+impl std::str::FromStr for ConvertFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        image::ImageFormat::from_extension(value.trim_start_matches('.').to_ascii_lowercase())
+            .filter(|format| format.writing_enabled())
+            .map(ConvertFormat)
+            .ok_or_else(|| format!("can't convert to '{}'; supported formats are {}", value, Self::SUPPORTED))
+    }
+}
+
+/// `X-OAuth-Scopes` lists a classic token's scopes separated by commas, e.g. `repo, read:org`.
+fn parse_oauth_scopes(header: &str) -> Vec<String> {
+    header.split(',').map(str::trim).filter(|scope| !scope.is_empty()).map(str::to_string).collect()
+}
+
+/// GitHub Enterprise Server serves the REST API under `/api/v3` on its own host.
+fn api_base(host: &str) -> String {
+    if host == DEFAULT_HOST {
+        "https://api.github.com".to_string()
+    } else {
+        format!("https://{}/api/v3", host)
+    }
+}
+
+/// Release asset IDs are plain positive integers, as the REST API returns them.
+fn is_release_asset_id(asset_id: &str) -> bool {
+    asset_id.chars().all(|c| c.is_ascii_digit()) && asset_id.parse::<u64>().is_ok_and(|id| id > 0)
+}
+
+/// An attachment found by [`AssetDownloader::list_assets`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ListedAsset {
+    pub asset_id: String,
+    pub filename: Option<String>,
+    /// In bytes, when the HEAD requests revealed it
+    pub size: Option<u64>,
+}
+
+/// How a local file compares with its asset, from [`AssetDownloader::verify`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Verification {
+    pub local_size: u64,
+    pub local_sha256: String,
+    /// The asset's size, from its Content-Length or, failing that, the bytes received
+    pub remote_size: u64,
+    /// None when the sizes already differ, so the body wasn't fetched
+    pub remote_sha256: Option<String>,
+}
+
+impl Verification {
+    pub fn matches(&self) -> bool {
+        self.local_size == self.remote_size && self.remote_sha256.as_deref() == Some(self.local_sha256.as_str())
+    }
+}
+
+const COMMENTS_PER_PAGE: usize = 100;
+
+/// The part of an issue, pull request or comment that attachments are found in.
+#[derive(serde::Deserialize)]
+struct IssueBody {
+    body: Option<String>,
+}
+
+/// The parts of a release that `release-download` needs: its assets' IDs and names.
+#[derive(serde::Deserialize)]
+struct Release {
+    assets: Vec<ReleaseAsset>,
+}
+
+#[derive(serde::Deserialize)]
+struct ReleaseAsset {
+    id: u64,
+    name: String,
+}
+
+/// A GitHub token, taken from the environment or the GitHub CLI.
+pub struct GitHubAuth {
+    token: String,
+}
+
+impl GitHubAuth {
+    /// Uses `GH_ASSET_TOKEN`, `GH_TOKEN` or `GITHUB_TOKEN` when set, so CI containers without gh
+    /// work too, and reads the token with `gh auth token` otherwise.
+    pub fn new() -> Result<Self> {
+        Self::for_host(DEFAULT_HOST)
+    }
+
+    /// Like [`GitHubAuth::new`], but asks gh for its credentials for a GitHub Enterprise Server
+    /// host instead of github.com.
+    pub fn for_host(host: &str) -> Result<Self> {
+        if let Some(token) = Self::token_from_env(|name| std::env::var(name).ok()) {
+            return Ok(GitHubAuth { token });
+        }
+
+        let output = Command::new("gh")
+            .args(["auth", "token", "--hostname", host])
+            .output()
+            .map_err(|e| DownloadError::AuthFailed(format!("Failed to execute gh command: {}. Set GH_ASSET_TOKEN, GH_TOKEN or GITHUB_TOKEN, or install GitHub CLI and run 'gh auth login'.", e)))?;
+
+        if !output.status.success() {
+            let error_msg = String::from_utf8_lossy(&output.stderr);
+            return Err(DownloadError::AuthFailed(format!(
+                "GitHub CLI authentication failed: {}. Set GH_ASSET_TOKEN, GH_TOKEN or GITHUB_TOKEN, or run 'gh auth login'.",
+                error_msg.trim()
+            )));
+        }
+
+        let token = String::from_utf8(output.stdout)
+            .map_err(|e| DownloadError::AuthFailed(format!("Failed to parse gh auth token output: {}", e)))?
+            .trim()
+            .to_string();
+
+        if token.is_empty() {
+            return Err(DownloadError::AuthFailed(
+                "GitHub CLI token is empty. Set GH_ASSET_TOKEN, GH_TOKEN or GITHUB_TOKEN, or run 'gh auth login' first.".to_string(),
+            ));
+        }
+
+        Ok(GitHubAuth { token })
+    }
+
+    fn token_from_env(lookup: impl Fn(&str) -> Option<String>) -> Option<String> {
+        TOKEN_ENV_VARS
+            .iter()
+            .filter_map(|name| lookup(name))
+            .map(|token| token.trim().to_string())
+            .find(|token| !token.is_empty())
+    }
+
+    fn get_token(&self) -> &str {
+        &self.token
+    }
+}
+
+/// Downloads GitHub attachments by asset ID.
+pub struct AssetDownloader {
+    auth: GitHubAuth,
+    config: Config,
+    /// Built on first use and shared by every download, including concurrent batch ones
+    client: std::sync::OnceLock<reqwest::Client>,
+    /// Keeps the bars of concurrent downloads on their own lines
+    progress: MultiProgress,
+    /// Where attachment URLs point instead of `https://<host>`, e.g. a mock server
+    base_url: Option<String>,
+    /// The files downloaded so far under `--dedupe`
+    dedupe_index: std::sync::Mutex<DedupeIndex>,
+}
+
+#[derive(Default)]
+struct DedupeIndex {
+    /// First copy of each content, keyed by directory and SHA-256
+    first_copies: HashMap<(PathBuf, String), PathBuf>,
+    collapsed: usize,
+}
+
+impl AssetDownloader {
+    /// Creates a downloader authenticated through the GitHub CLI.
+    pub fn new(config: Config) -> Result<Self> {
+        let auth = GitHubAuth::for_host(config.host.as_deref().unwrap_or(DEFAULT_HOST))?;
+        Ok(Self::with_auth(auth, config))
+    }
+
+    pub fn with_auth(auth: GitHubAuth, config: Config) -> Self {
+        AssetDownloader {
+            auth,
+            config,
+            client: std::sync::OnceLock::new(),
+            progress: MultiProgress::new(),
+            base_url: None,
+            dedupe_index: std::sync::Mutex::default(),
+        }
+    }
+
+    /// Like [`AssetDownloader::with_auth`], but fetches attachments from `base_url` (such as
+    /// `http://127.0.0.1:8080`) instead of the configured host, for testing against a mock
+    /// server.
+    pub fn with_base_url(auth: GitHubAuth, config: Config, base_url: impl Into<String>) -> Self {
+        let base_url = base_url.into().trim_end_matches('/').to_string();
+        AssetDownloader { base_url: Some(base_url), ..Self::with_auth(auth, config) }
+    }
+
+    pub fn config_mut(&mut self) -> &mut Config {
+        &mut self.config
+    }
+
+    fn host(&self) -> &str {
+        self.config.host.as_deref().unwrap_or(DEFAULT_HOST)
+    }
+
+    fn base_url(&self) -> String {
+        match &self.base_url {
+            Some(base_url) => base_url.clone(),
+            None => format!("https://{}", self.host()),
+        }
+    }
+
+    /// Redirects aren't followed automatically: the HEAD probe reads the redirect itself, and
+    /// GET follows it by hand in `get_following_redirects`.
+    fn client(&self) -> Result<&reqwest::Client> {
+        if let Some(client) = self.client.get() {
+            return Ok(client);
+        }
+        let user_agent = self.config.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT);
+        let mut builder = reqwest::Client::builder()
+            .user_agent(user_agent)
+            .redirect(reqwest::redirect::Policy::none());
+        // The one client serves the HEAD probe, every GET and the API calls alike
+        if let Some(seconds) = self.config.connect_timeout {
+            builder = builder.connect_timeout(std::time::Duration::from_secs(seconds));
+        }
+        // A read timeout restarts after every read, unlike the per-request total timeout. The
+        // body is only read while the stream is polled, so --limit-rate pauses don't count.
+        match self.config.stall_timeout.unwrap_or(DEFAULT_STALL_TIMEOUT_SECS) {
+            0 => {}
+            seconds => builder = builder.read_timeout(std::time::Duration::from_secs(seconds)),
+        }
+        // Without one, reqwest reads the proxy from the environment itself
+        if let Some(proxy) = &self.config.proxy {
+            let proxy = reqwest::Proxy::all(proxy)
+                .map_err(|e| DownloadError::network(format!("Invalid proxy '{}'", proxy), e))?
+                .no_proxy(reqwest::NoProxy::from_env());
+            builder = builder.proxy(proxy);
+        }
+        let client = builder
+            .build()
+            .map_err(|e| DownloadError::network("Failed to create HTTP client", e))?;
+        Ok(self.client.get_or_init(|| client))
+    }
+
+    /// Downloads each asset into the destination directory, up to `--concurrency` at a time. A
+    /// failure doesn't stop the rest; a summary is printed at the end, in the order the IDs were
+    /// given, and an error returned if anything failed.
+    pub async fn download_many(&self, asset_ids: &[String], destination: &str) -> Result<()> {
+        let mut results: Vec<_> = futures_util::stream::iter(asset_ids.iter().enumerate())
+            .map(|(index, asset_id)| async move {
+                let result = self.download_one(asset_id, destination, false).await;
+                if let Err(e) = &result {
+                    if self.config.json {
+                        let mut error = e.to_json();
+                        error["asset_id"] = asset_id.as_str().into();
+                        self.progress.suspend(|| eprintln!("{}", error));
+                    } else {
+                        self.progress.suspend(|| eprintln!("Failed to download {}: {}", asset_id, e));
+                    }
+                }
+                (index, asset_id.as_str(), result)
+            })
+            .buffer_unordered(self.config.concurrency.max(1))
+            .collect()
+            .await;
+        results.sort_by_key(|(index, _, _)| *index);
+
+        // An asset left alone (--no-clobber, --on-html skip, ...) is neither downloaded nor failed
+        let mut succeeded = Vec::new();
+        let mut skipped = Vec::new();
+        let mut failed = Vec::new();
+        for (_, asset_id, result) in results {
+            match result {
+                Ok(Some(_)) => succeeded.push(asset_id),
+                Ok(None) => skipped.push(asset_id),
+                Err(e) => failed.push((asset_id, e)),
+            }
+        }
+
+        if skipped.is_empty() {
+            self.status(format_args!("\nDownloaded {} of {} assets", succeeded.len(), asset_ids.len()));
+        } else {
+            self.status(format_args!("\nDownloaded {} of {} assets ({} skipped)", succeeded.len(), asset_ids.len(), skipped.len()));
+        }
+        for asset_id in &succeeded {
+            self.status(format_args!("  ok      {}", asset_id));
+        }
+        for asset_id in &skipped {
+            self.status(format_args!("  skipped {}", asset_id));
+        }
+        for (asset_id, e) in &failed {
+            self.status(format_args!("  failed  {}: {}", asset_id, e));
+        }
+        if self.config.dedupe {
+            let collapsed = self.dedupe_index.lock().unwrap().collapsed;
+            self.status(format_args!("{} duplicate(s) replaced with links", collapsed));
+        }
+
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(DownloadError::BatchFailed { failed: failed.len(), total: asset_ids.len() })
+        }
+    }
+
+    /// Like [`AssetDownloader::download_many`], but bundles the files into one archive instead
+    /// of leaving them loose. The format follows the extension: `.zip`, `.tar`, or `.tar.gz`/`.tgz`.
+    /// Assets are downloaded into a hidden directory next to the archive, which is removed once
+    /// they are packed; entries are named like directory downloads. Returns the archive's path.
+    pub async fn download_into_archive(&self, asset_ids: &[String], archive: &str) -> Result<PathBuf> {
+        let archive = self.validate_destination_path(archive)?;
+        let kind = self.output_archive_kind(&archive)?;
+        if archive.exists() && !self.config.overwrite {
+            return Err(DownloadError::AlreadyExists(archive));
+        }
+        let filename = archive.file_name().unwrap_or_default().to_string_lossy();
+        let staging = archive.with_file_name(format!(".{}.assets", filename));
+        std::fs::create_dir_all(&staging)
+            .map_err(|e| DownloadError::io(format!("Failed to create {}", staging.display()), e))?;
+
+        // Whatever did download is still packed when some assets failed
+        let downloaded = self.download_many(asset_ids, &staging.to_string_lossy()).await;
+        let packed = self.pack_archive(kind, &staging, &archive);
+        let _ = std::fs::remove_dir_all(&staging);
+        let count = packed?;
+        self.status(format_args!("Packed {} file(s) into {}", count, archive.display()));
+        downloaded.map(|_| archive)
+    }
+
+    /// Finds every attachment in the body of an issue or pull request, along with the filename
+    /// a directory download would give it. Assets whose type can't be probed have no filename.
+    pub async fn list_assets(&self, issue: &IssueRef) -> Result<Vec<ListedAsset>> {
+        let asset_ids = self.issue_asset_ids(issue, false).await?;
+        self.describe(&asset_ids, false).await
+    }
+
+    /// Finds the filename a directory download would give each asset, and its size, with HEAD
+    /// requests. Unlike [`list_assets`](Self::list_assets), this follows GitHub's redirect with
+    /// a second HEAD to the storage host for the size. Either is None when the server doesn't
+    /// reveal it.
+    pub async fn describe_assets(&self, asset_ids: &[String]) -> Result<Vec<ListedAsset>> {
+        self.describe(asset_ids, true).await
+    }
+
+    async fn describe(&self, asset_ids: &[String], ask_storage: bool) -> Result<Vec<ListedAsset>> {
+        let mut assets = Vec::new();
+        for asset_id in asset_ids {
+            let url = self.build_asset_url(asset_id)?;
+            let (filename, size) = match self.send_head(&url).await {
+                Ok(response) => {
+                    let resolution = self.resolution_from_head(&response);
+                    let filename = self
+                        .resolve_final_path(Path::new("."), asset_id, Some(&resolution.chosen))
+                        .file_name()
+                        .map(|name| name.to_string_lossy().into_owned());
+                    (filename, self.head_size(&response, ask_storage).await)
+                }
+                Err(e) => {
+                    eprintln!("Warning: could not detect the type of {}: {}", asset_id, e);
+                    (None, None)
+                }
+            };
+            assets.push(ListedAsset { asset_id: asset_id.clone(), filename, size });
+        }
+        Ok(assets)
+    }
+
+    /// Asks the API who the token belongs to, for `doctor`: this shows the host is reachable and
+    /// the token accepted. Returns the OAuth scopes GitHub reports for it, or None for tokens
+    /// that have none, such as fine-grained and GitHub App tokens.
+    pub async fn token_scopes(&self) -> Result<Option<Vec<String>>> {
+        let url = format!("{}/user", api_base(self.host()));
+        let (response, _) = self.get_following_redirects(&url, Some(std::time::Duration::from_secs(30)), None, None).await?;
+        let status = response.status();
+        if status == reqwest::StatusCode::UNAUTHORIZED {
+            return Err(DownloadError::AuthFailed(format!("{} rejected the token (HTTP 401)", self.host())));
+        }
+        if !status.is_success() {
+            return Err(DownloadError::HttpStatus {
+                status: status.as_u16(),
+                message: format!("Failed to fetch the token's user: HTTP {}", status),
+            });
+        }
+        let scopes = response.headers().get("x-oauth-scopes").and_then(|v| v.to_str().ok());
+        Ok(scopes.map(parse_oauth_scopes))
+    }
+
+    /// Compares `local` with the asset's current bytes by size and SHA-256. The asset is hashed
+    /// as it streams in, so nothing is written, and the local file is left untouched.
+    pub async fn verify(&self, asset_id: &str, local: &Path) -> Result<Verification> {
+        let asset_id = self.clean_asset_id(asset_id)?;
+        let url = self.build_asset_url(&asset_id)?;
+        let (local_size, local_sha256) = {
+            let mut file = File::open(local)
+                .map_err(|e| DownloadError::io(format!("Failed to open {}", local.display()), e))?;
+            let mut hasher = Sha256::new();
+            let size = std::io::copy(&mut file, &mut hasher)
+                .map_err(|e| DownloadError::io(format!("Failed to read {}", local.display()), e))?;
+            (size, format!("{:x}", hasher.finalize()))
+        };
+
+        let (response, chain) = self.get_following_redirects(&url, self.timeout_for_attempt(0), None, None).await?;
+        let status = response.status();
+        if !status.is_success() {
+            let mut message = format!(
+                "HTTP request failed with status: {} - {}",
+                status,
+                status.canonical_reason().unwrap_or("Unknown error")
+            );
+            if chain.redirected() {
+                message = format!("{} (redirects: {})", message, chain);
+            }
+            return Err(DownloadError::HttpStatus { status: status.as_u16(), message });
+        }
+        if let Some(remote_size) = response.content_length().filter(|&len| len != local_size) {
+            return Ok(Verification { local_size, local_sha256, remote_size, remote_sha256: None });
+        }
+
+        let mut hasher = Sha256::new();
+        let mut remote_size = 0;
+        let mut stream = response.bytes_stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|e| DownloadError::network("Failed to read response body", e))?;
+            remote_size += chunk.len() as u64;
+            hasher.update(&chunk);
+        }
+        let remote_sha256 = Some(format!("{:x}", hasher.finalize()));
+        Ok(Verification { local_size, local_sha256, remote_size, remote_sha256 })
+    }
+
+    /// Collects the attachment IDs in an issue or pull request body, and optionally in its
+    /// comments too, in the order they appear.
+    pub async fn issue_asset_ids(&self, issue: &IssueRef, include_comments: bool) -> Result<Vec<String>> {
+        let mut text = self.fetch_issue_body(issue).await?;
+        if include_comments {
+            for comment in self.fetch_issue_comments(issue).await? {
+                text.push('\n');
+                text.push_str(&comment);
+            }
+        }
+        Ok(self.extract_attachment_ids(&text))
+    }
+
+    /// Issues and pull requests share the issues endpoint, so either works here.
+    async fn fetch_issue_body(&self, issue: &IssueRef) -> Result<String> {
+        let issue_body: IssueBody = self.fetch_api(&issue.to_string(), &issue.api_url(self.host())).await?;
+        Ok(issue_body.body.unwrap_or_default())
+    }
+
+    /// Pages through the conversation comments, 100 at a time.
+    async fn fetch_issue_comments(&self, issue: &IssueRef) -> Result<Vec<String>> {
+        let mut bodies = Vec::new();
+        for page in 1.. {
+            let url = format!("{}/comments?per_page={}&page={}", issue.api_url(self.host()), COMMENTS_PER_PAGE, page);
+            let comments: Vec<IssueBody> = self.fetch_api(&issue.to_string(), &url).await?;
+            let last_page = comments.len() < COMMENTS_PER_PAGE;
+            bodies.extend(comments.into_iter().filter_map(|comment| comment.body));
+            if last_page {
+                break;
+            }
+        }
+        Ok(bodies)
+    }
+
+    /// GETs a REST API resource as JSON. `subject` names it in error messages.
+    async fn fetch_api<T: serde::de::DeserializeOwned>(&self, subject: &str, url: &str) -> Result<T> {
+        let (response, _) = self
+            .get_following_redirects(url, Some(std::time::Duration::from_secs(30)), None, None)
+            .await
+            .map_err(|e| match e {
+                DownloadError::Network { error, .. } => DownloadError::network(format!("Failed to fetch {}", subject), error),
+                other => other,
+            })?;
+
+        let status = response.status();
+        if status == reqwest::StatusCode::NOT_FOUND {
+            return Err(DownloadError::HttpStatus {
+                status: status.as_u16(),
+                message: format!("{} not found, or the token has no access to it", subject),
+            });
+        }
+        if !status.is_success() {
+            return Err(DownloadError::HttpStatus {
+                status: status.as_u16(),
+                message: format!(
+                    "Failed to fetch {}: HTTP {} - {}",
+                    subject,
+                    status,
+                    status.canonical_reason().unwrap_or("Unknown error")
+                ),
+            });
+        }
+
+        response
+            .json()
+            .await
+            .map_err(|e| DownloadError::InvalidResponse(format!("Failed to parse the response for {}: {}", subject, e)))
+    }
+
+    /// Looks up the numeric ID of the asset called `name` in the release tagged `tag`.
+    pub async fn release_asset_id(&self, repo: &RepoRef, tag: &str, name: &str) -> Result<String> {
+        let subject = format!("release {} of {}", tag, repo);
+        let release: Release = match self.fetch_api(&subject, &repo.release_by_tag_url(self.host(), tag)).await {
+            Err(DownloadError::HttpStatus { status: 404, .. }) => {
+                return Err(DownloadError::HttpStatus {
+                    status: 404,
+                    message: format!("No release tagged '{}' in {}, or the token has no access to it", tag, repo),
+                })
+            }
+            result => result?,
+        };
+        self.pick_release_asset(&release, &subject, name)
+    }
+
+    /// Release asset names are unique within a release, so an exact match is the one.
+    fn pick_release_asset(&self, release: &Release, subject: &str, name: &str) -> Result<String> {
+        if let Some(asset) = release.assets.iter().find(|asset| asset.name == name) {
+            return Ok(asset.id.to_string());
+        }
+        let available = match release.assets.is_empty() {
+            true => "it has none".to_string(),
+            false => format!("it has {}", release.assets.iter().map(|asset| asset.name.as_str()).collect::<Vec<_>>().join(", ")),
+        };
+        Err(DownloadError::InvalidAssetId(format!("No asset named '{}' in {} ({})", name, subject, available)))
+    }
+
+    /// Downloads the asset called `name` from the release tagged `tag` through the releases
+    /// API. A directory destination gets the asset's own name rather than its numeric ID.
+    pub async fn download_release_asset(&self, repo: &RepoRef, tag: &str, name: &str, destination: &str) -> Result<Option<DownloadOutcome>> {
+        let asset_id = self.release_asset_id(repo, tag, name).await?;
+        let mut destination_path = self.validate_destination_path(destination)?;
+        if destination_path.is_dir() && !self.is_stdout(&destination_path) {
+            let filename = self
+                .sanitize_filename(name)
+                .ok_or_else(|| DownloadError::PathRejected(format!("Release asset name '{}' can't be used as a filename", name)))?;
+            destination_path = destination_path.join(filename);
+        }
+        let url = repo.release_asset_url(self.host(), &asset_id);
+        let outcome = self.fetch_url(&asset_id, &url, destination_path, true).await?;
+        Ok(outcome.map(|outcome| DownloadOutcome { repo: self.config.repo.clone(), ..outcome }))
+    }
+
+    /// Downloads one asset to `destination`, a file path, a directory to name it in, or `-` for
+    /// stdout. Returns where it was saved, or None when it was skipped, e.g. by `only_if_type`.
+    /// Under `dry_run`, returns where it would be saved instead.
+    pub async fn download(&self, asset_id: &str, destination: &str) -> Result<Option<DownloadOutcome>> {
+        self.download_one(asset_id, destination, true).await
+    }
+
+    /// Without `probe`, a directory download skips the HEAD request and names the file from the
+    /// GET response's headers instead, saving a round trip per asset in batches.
+    async fn download_one(&self, asset_id: &str, destination: &str, probe: bool) -> Result<Option<DownloadOutcome>> {
+        let outcome = self.fetch(asset_id, destination, probe).await?;
+        Ok(outcome.map(|outcome| DownloadOutcome { repo: self.config.repo.clone(), ..outcome }))
+    }
+
+    async fn fetch(&self, asset_id: &str, destination: &str, probe: bool) -> Result<Option<DownloadOutcome>> {
+        let asset_id = self.clean_asset_id(asset_id)?;
+        let asset_id = asset_id.as_str();
+        let url = self.build_asset_url(asset_id)?;
+        let destination_path = self.validate_destination_path(destination)?;
+        self.fetch_url(asset_id, &url, destination_path, probe).await
+    }
+
+    /// Fetches `url` into a destination that has already been validated.
+    async fn fetch_url(&self, asset_id: &str, url: &str, destination_path: PathBuf, probe: bool) -> Result<Option<DownloadOutcome>> {
+        self.check_writable(&destination_path)?;
+        match self.config.transport {
+            // gh only reveals the type once the whole body is fetched, so a dry run probes
+            // with a HEAD request either way
+            Transport::Gh if !self.config.dry_run => self.download_with_gh(url, &destination_path, asset_id).await,
+            Transport::Reqwest | Transport::Gh => {
+                // The type is only needed to name directory downloads or to apply --only-if-type.
+                // Some servers reject HEAD, so a failed probe leaves it to the GET's headers.
+                let mut type_pending = false;
+                let needs_type = (destination_path.is_dir() && !self.is_stdout(&destination_path)) || !self.config.only_if_type.is_empty();
+                let detected = if let Some(overridden) = self.get_overridden_type(asset_id) {
+                    Some(overridden)
+                } else if needs_type && !probe && self.can_skip_probe() {
+                    type_pending = true;
+                    None
+                } else if needs_type {
+                    match self.resolve_extension(url).await {
+                        Ok(resolution) => {
+                            self.print_extension_candidates(asset_id, &resolution);
+                            Some(resolution.chosen)
+                        }
+                        Err(e) => {
+                            self.status(format_args!("Could not probe the type of {} ({}); detecting it from the download instead", asset_id, e));
+                            type_pending = true;
+                            None
+                        }
+                    }
+                } else {
+                    None
+                };
+
+                if let Some(detected) = &detected {
+                    if !self.matches_type_filter(&detected.extension, detected.mime_type.as_deref()) {
+                        self.status(format_args!("Skipping {}: detected type {} does not match --only-if-type", asset_id, detected.extension));
+                        return Ok(None);
+                    }
+                }
+
+                // There is no filename to generate when writing to stdout
+                let final_path = if self.is_stdout(&destination_path) {
+                    destination_path.clone()
+                } else {
+                    self.resolve_final_path(&destination_path, asset_id, detected.as_ref())
+                };
+                if self.skip_missing_for_refresh(asset_id, &final_path) {
+                    return Ok(None);
+                }
+                let auto_named = destination_path.is_dir() && !self.is_stdout(&destination_path);
+                self.print_naming_trace(asset_id, detected.as_ref().filter(|_| auto_named), &final_path);
+                if self.config.dry_run {
+                    let content_type = detected.and_then(|d| d.mime_type);
+                    return Ok(Some(DownloadOutcome::new(final_path, 0, content_type)));
+                }
+                self.download_with_reqwest(asset_id, url, &final_path, auto_named, type_pending).await
+            }
+        }
+    }
+
+    /// The GET's headers only correct the extension of a generated name, so the probe is still
+    /// needed when the name or a decision depends on more than that before the body arrives:
+    /// dry runs, name templates and original names, refreshing only existing files, and
+    /// resuming a partial file under its final name.
+    fn can_skip_probe(&self) -> bool {
+        let config = &self.config;
+        !config.dry_run
+            && config.rename.is_none()
+            && !config.use_original_name
+            && !config.preserve_query_filename
+            && !config.replace_existing_only
+            && !config.resume
+    }
+
+    fn build_asset_url(&self, asset_id: &str) -> Result<String> {
+        if self.config.no_verify_asset_id {
+            if !self.is_url_safe_asset_id(asset_id) {
+                return Err(DownloadError::InvalidAssetId(format!(
+                    "Invalid asset ID '{}': only letters, digits, '-', '_' and '.' are allowed",
+                    asset_id
+                )));
+            }
+        } else if !self.is_valid_asset_id(asset_id) {
+            let expected = match self.config.release_repo {
+                Some(_) => "xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx, or a numeric release asset ID",
+                None => "xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx",
+            };
+            return Err(DownloadError::InvalidAssetId(format!("Invalid asset ID format. Expected format: {}", expected)));
+        }
+
+        match &self.config.release_repo {
+            Some(repo) if is_release_asset_id(asset_id) => Ok(repo.release_asset_url(self.host(), asset_id)),
+            _ => Ok(format!("{}/user-attachments/assets/{}", self.base_url(), asset_id)),
+        }
+    }
+
+    /// The Accept header for fetching the asset itself: `--accept` if given. Attachments are
+    /// plain files and need none, but the releases API answers with the asset's metadata as
+    /// JSON unless asked for the bytes, and then redirects to the storage host.
+    fn accept_header(&self, url: &str) -> Option<&str> {
+        if let Some(accept) = &self.config.accept {
+            return Some(accept);
+        }
+        url.contains("/releases/assets/").then_some("application/octet-stream")
+    }
+
+    /// The `--header` values, minus any Authorization, which only ever carries the token.
+    fn extra_headers(&self) -> impl Iterator<Item = &(String, String)> {
+        self.config.headers.iter().filter(|(name, _)| !name.eq_ignore_ascii_case("authorization"))
+    }
+
+    fn with_extra_headers(&self, mut request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        for (name, value) in self.extra_headers() {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        request
+    }
+
+    /// Reads asset IDs or attachment URLs one per line, as piped to `--stdin`, skipping blank
+    /// lines and `#` comments. Lines that don't hold a valid ID come back separately with their
+    /// 1-based line number, so one bad line doesn't stop the rest.
+    pub fn parse_asset_id_list(&self, text: &str) -> (Vec<String>, Vec<(usize, DownloadError)>) {
+        let mut asset_ids = Vec::new();
+        let mut malformed = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match self.clean_asset_id(line).and_then(|asset_id| self.build_asset_url(&asset_id).map(|_| asset_id)) {
+                Ok(asset_id) => asset_ids.push(asset_id),
+                Err(e) => malformed.push((index + 1, e)),
+            }
+        }
+        (asset_ids, malformed)
+    }
+
+    /// Picks the one asset out of pasted text, as read from the clipboard by `--from-clipboard`:
+    /// a bare ID, or an attachment URL or UUID anywhere in the text.
+    pub fn find_asset_id(&self, text: &str) -> Result<String> {
+        let text = text.trim();
+        if self.is_valid_asset_id(text) {
+            return Ok(text.to_string());
+        }
+        let mut ids = self.extract_asset_ids(text);
+        match ids.len() {
+            0 => Err(DownloadError::InvalidAssetId("no asset reference found".to_string())),
+            1 => Ok(ids.remove(0)),
+            _ => Err(DownloadError::InvalidAssetId(format!("found several asset IDs: {}", ids.join(", ")))),
+        }
+    }
+
+    /// Accepts a full attachment URL, or a markdown image of one, in place of the bare ID.
+    /// Under `--lenient-id`, also digs the asset ID out of any other pasted text. Otherwise the
+    /// input is used as-is.
+    fn clean_asset_id(&self, input: &str) -> Result<String> {
+        if self.is_valid_asset_id(input) {
+            return Ok(input.to_string());
+        }
+        if let Some(asset_id) = self.asset_id_from_url(input) {
+            return Ok(asset_id);
+        }
+        if !self.config.lenient_id {
+            return Ok(input.to_string());
+        }
+
+        let mut ids = self.extract_asset_ids(input);
+        let asset_id = match ids.len() {
+            0 => return Err(DownloadError::InvalidAssetId(format!("No asset ID found in '{}'", input))),
+            1 => ids.remove(0),
+            _ => {
+                return Err(DownloadError::InvalidAssetId(format!(
+                    "Found several asset IDs in '{}': {}",
+                    input,
+                    ids.join(", ")
+                )))
+            }
+        };
+
+        if self.config.verbose {
+            eprintln!("Extracted asset ID {} from '{}'", asset_id, input);
+        }
+        Ok(asset_id)
+    }
+
+    /// Matches `https://github.com/user-attachments/assets/<id>` (or the `--host` equivalent) on
+    /// its own or wrapped as `![alt](...)`, and nothing else, so a typo is still reported as an
+    /// invalid ID.
+    fn asset_id_from_url(&self, input: &str) -> Option<String> {
+        let re = Regex::new(&format!(
+            r"^(?:!\[[^\]]*\]\()?(?:https?://)?{}/user-attachments/assets/([A-Za-z0-9\-]+)/?(?:[?#][^)\s]*)?\)?$",
+            regex::escape(self.host())
+        ))
+        .unwrap();
+        let input = input.trim();
+        // Only strip the closing parenthesis when the markdown opener is there too
+        if input.starts_with('!') != input.ends_with(')') {
+            return None;
+        }
+        re.captures(input)
+            .map(|c| c[1].to_string())
+            .filter(|asset_id| self.is_valid_asset_id(asset_id))
+    }
+
+    /// Finds asset IDs in free text: user-attachments URLs first, then bare UUIDs. Duplicates are
+    /// dropped while keeping first-seen order.
+    fn extract_asset_ids(&self, text: &str) -> Vec<String> {
+        let uuid_re = Regex::new(r"\b[a-fA-F0-9]{8}-[a-fA-F0-9]{4}-[a-fA-F0-9]{4}-[a-fA-F0-9]{4}-[a-fA-F0-9]{12}\b").unwrap();
+
+        let mut ids = self.extract_attachment_ids(text);
+        for id in uuid_re.find_iter(text).map(|m| m.as_str().to_string()) {
+            if self.is_valid_asset_id(&id) && !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+        ids
+    }
+
+    /// Finds the IDs of user-attachments URLs only, in first-seen order without duplicates.
+    fn extract_attachment_ids(&self, text: &str) -> Vec<String> {
+        let url_re = Regex::new(r"user-attachments/assets/([A-Za-z0-9][A-Za-z0-9\-]*[A-Za-z0-9])").unwrap();
+
+        let mut ids: Vec<String> = Vec::new();
+        for id in url_re.captures_iter(text).map(|c| c[1].to_string()) {
+            if self.is_valid_asset_id(&id) && !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+        ids
+    }
+
+    /// The minimal check under `--no-verify-asset-id`: the ID must stay a single path segment
+    /// and be usable in a filename.
+    fn is_url_safe_asset_id(&self, asset_id: &str) -> bool {
+        !asset_id.is_empty()
+            && asset_id != "."
+            && asset_id != ".."
+            && asset_id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    }
+
+    /// Attachment IDs are UUIDs. With a release repository configured, numeric release asset
+    /// IDs are accepted too.
+    fn is_valid_asset_id(&self, asset_id: &str) -> bool {
+        let uuid_re = Regex::new(r"^[a-fA-F0-9]{8}-[a-fA-F0-9]{4}-[a-fA-F0-9]{4}-[a-fA-F0-9]{4}-[a-fA-F0-9]{12}$").unwrap();
+        uuid_re.is_match(asset_id) || (self.config.release_repo.is_some() && is_release_asset_id(asset_id))
+    }
+
+    /// Checks that a relative destination stays inside the current directory, or inside
+    /// `root` when one is configured, and returns it resolved. Neither reaches into a system
+    /// directory that the root isn't already in.
+    pub fn validate_destination_path(&self, destination: &str) -> Result<PathBuf> {
+        let path = Path::new(destination);
+        // A pipe or device is written straight through, so there is no file to contain
+        if self.is_stream(path) {
+            return Ok(path.to_path_buf());
+        }
+
+        // Check for path traversal attempts. Only a whole `..` component counts, with either
+        // separator on Windows, so names like `my..backup.png` are fine. With --root, `..` is
+        // allowed and the containment check below keeps the result inside the chosen root
+        // instead. With --allow-outside-cwd it is allowed outright.
+        let has_parent_dir = path.components().any(|c| c == std::path::Component::ParentDir);
+        if self.config.root.is_none() && !self.config.allow_outside_cwd && has_parent_dir {
+            return Err(DownloadError::PathRejected("Path traversal detected in destination path".to_string()));
+        }
+
+        // Ensure absolute paths don't lead into system directories. They're also checked resolved,
+        // following `..` and symlinks, so `/tmp/../etc` or a link to /etc is caught like `/etc`
+        let into_system_dir = path.is_absolute()
+            && (self.is_system_dir(path) || self.is_system_dir(&self.canonicalize_nearest(path)?));
+        if into_system_dir {
+            return Err(DownloadError::PathRejected("Access to system directories is not allowed".to_string()));
+        }
+
+        // Canonicalize the path to resolve any remaining traversal attempts
+        let current_dir = std::env::current_dir()
+            .map_err(|e| DownloadError::io("Failed to get current directory", e))?;
+
+        let resolved_path = if path.is_relative() {
+            current_dir.join(path)
+        } else {
+            path.to_path_buf()
+        };
+
+        // With --allow-outside-cwd a relative path may leave the current directory for anywhere
+        // except the system directories. Absolute paths were resolved and checked above
+        if path.is_relative() && self.config.allow_outside_cwd {
+            let canonical = self.canonicalize_nearest(&resolved_path)?;
+            let inside_cwd = current_dir.canonicalize().is_ok_and(|cwd| canonical.starts_with(cwd));
+            if !inside_cwd && self.is_system_dir(&canonical) {
+                return Err(DownloadError::PathRejected("Access to system directories is not allowed".to_string()));
+            }
+        }
+
+        // Ensure the resolved path is within or below the root (the current directory unless
+        // --root is given) for relative paths
+        if path.is_relative() && !self.config.allow_outside_cwd {
+            let (root, root_name) = match &self.config.root {
+                Some(root) => (root.as_path(), root.display().to_string()),
+                None => (current_dir.as_path(), "current directory".to_string()),
+            };
+            let root = root
+                .canonicalize()
+                .map_err(|e| DownloadError::io(format!("Failed to resolve {}", root_name), e))?;
+            let canonical = self.canonicalize_nearest(&resolved_path)?;
+            if !canonical.starts_with(&root) {
+                return Err(DownloadError::PathRejected(format!("Destination path must be within {}", root_name)));
+            }
+            // A broad root like `--root /` would otherwise let `../../etc` through
+            if self.is_system_dir(&canonical) && !self.is_system_dir(&root) {
+                return Err(DownloadError::PathRejected("Access to system directories is not allowed".to_string()));
+            }
+        }
+
+        // Check filename for invalid characters
+        if let Some(filename) = path.file_name() {
+            let filename_str = filename.to_string_lossy();
+            if filename_str.contains('\0') || filename_str.trim().is_empty() {
+                return Err(DownloadError::PathRejected("Invalid filename".to_string()));
+            }
+        }
+
+        Ok(resolved_path)
+    }
+
+    /// Fails before any bytes are transferred when the download couldn't be saved. The directory
+    /// it goes into must exist, or under `create_dirs` the nearest existing one it would be
+    /// created under, and be a directory a file can be created in.
+    fn check_writable(&self, destination: &Path) -> Result<()> {
+        if self.is_stream(destination) {
+            return Ok(());
+        }
+
+        let mut dir = if destination.is_dir() {
+            destination
+        } else {
+            destination.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."))
+        };
+        if !dir.exists() && !self.config.create_dirs {
+            return Err(DownloadError::PathRejected(format!(
+                "directory {} does not exist. Create it first, or pass --create-dirs",
+                dir.display()
+            )));
+        }
+        while !dir.exists() {
+            match dir.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                Some(parent) => dir = parent,
+                None => {
+                    dir = Path::new(".");
+                    break;
+                }
+            }
+        }
+        if !dir.is_dir() {
+            return Err(DownloadError::PathRejected(format!("{} is not a directory", dir.display())));
+        }
+
+        let probe = dir.join(format!(".gh-asset-{}.tmp", uuid::Uuid::new_v4()));
+        match std::fs::OpenOptions::new().write(true).create_new(true).open(&probe) {
+            Ok(_) => {
+                let _ = std::fs::remove_file(&probe);
+                Ok(())
+            }
+            Err(e) => Err(DownloadError::PathRejected(format!(
+                "destination directory {} is not writable: {}",
+                dir.display(),
+                e
+            ))),
+        }
+    }
+
+    /// Matches whole path components, so `/usr` covers `/usr/bin` but not `/usrdata`. Windows
+    /// paths are compared case-insensitively and without the `\\?\` prefix canonicalize adds.
+    /// `.` and `..` are resolved lexically first, so `/usr/../etc` counts as `/etc`; callers
+    /// that need symlinks followed pass the path through `canonicalize_nearest`.
+    fn is_system_dir(&self, path: &Path) -> bool {
+        let normalize = |path: &Path| {
+            let path = path.to_string_lossy();
+            let path = path.strip_prefix(r"\\?\").unwrap_or(&path);
+            let path = PathBuf::from(if cfg!(windows) { path.to_lowercase() } else { path.to_string() });
+            let mut normalized = PathBuf::new();
+            for component in path.components() {
+                match component {
+                    std::path::Component::CurDir => {}
+                    std::path::Component::ParentDir => {
+                        normalized.pop();
+                    }
+                    other => normalized.push(other),
+                }
+            }
+            normalized
+        };
+        let path = normalize(path);
+        system_dirs().iter().any(|dir| path.starts_with(normalize(dir)))
+    }
+
+    /// Canonicalizes the deepest existing ancestor of `path` and appends the rest, so paths that
+    /// don't exist yet (including ones with `..` in them) can still be checked for containment.
+    /// A `..` after an existing prefix goes up from wherever that prefix really is, as the OS
+    /// would, so `link/../x` ends up next to the link's target rather than next to the link.
+    fn canonicalize_nearest(&self, path: &Path) -> Result<PathBuf> {
+        let canonicalize = |path: &Path| {
+            path.canonicalize()
+                .map_err(|e| DownloadError::io("Failed to validate destination path", e))
+        };
+        let mut normalized = PathBuf::new();
+        for component in path.components() {
+            match component {
+                std::path::Component::CurDir => {}
+                std::path::Component::ParentDir => {
+                    if normalized.exists() {
+                        normalized = canonicalize(&normalized)?;
+                    }
+                    normalized.pop();
+                }
+                other => normalized.push(other),
+            }
+        }
+
+        let mut existing = normalized.as_path();
+        let mut missing = Vec::new();
+        while !existing.exists() {
+            let (Some(parent), Some(name)) = (existing.parent(), existing.file_name()) else {
+                break;
+            };
+            missing.push(name);
+            existing = parent;
+        }
+
+        let mut canonical = canonicalize(existing)?;
+        canonical.extend(missing.into_iter().rev());
+        Ok(canonical)
+    }
+
+    fn resolve_final_path(&self, destination: &Path, asset_id: &str, detected: Option<&DetectedType>) -> PathBuf {
+        let final_path = if destination.is_dir() {
+            let extension = detected.map(|d| d.extension.as_str()).unwrap_or(".bin");
+            let filename = match (&self.config.rename, self.original_filename(detected)) {
+                (Some(template), _) => {
+                    let original = detected.and_then(|d| d.disposition_filename.as_deref().or(d.query_filename.as_deref()));
+                    let date = chrono::Local::now().format("%Y-%m-%d").to_string();
+                    template.render(asset_id, extension, original, &date)
+                }
+                (None, Some(filename)) => filename.to_string(),
+                (None, None) => format!("{}{}", asset_id, extension),
+            };
+            destination.join(filename)
+        } else {
+            destination.to_path_buf()
+        };
+
+        if cfg!(windows) {
+            if let Some(trimmed) = self.trim_windows_filename(&final_path) {
+                eprintln!(
+                    "Warning: Windows does not allow filenames ending in a dot or space; saving as {}",
+                    trimmed.display()
+                );
+                return trimmed;
+            }
+        }
+
+        final_path
+    }
+
+    /// An explicit filename is used as given, so a JPEG saved as `photo.png` keeps the wrong
+    /// extension. Warns about it, or fails under `--strict`. Types only guessed from the fallback
+    /// or set with `--content-type-override` are not checked.
+    fn check_extension_matches(&self, destination: &Path, detected: &DetectedType) -> Result<()> {
+        if matches!(detected.source, TypeSource::Fallback | TypeSource::Override) {
+            return Ok(());
+        }
+        let Some(wanted) = destination.extension().map(|e| e.to_string_lossy().to_ascii_lowercase()) else {
+            return Ok(());
+        };
+        let actual = detected.extension.trim_start_matches('.').to_ascii_lowercase();
+        let (Some(wanted_type), Some(actual_type)) =
+            (self.get_mime_type_from_extension(&wanted), self.get_mime_type_from_extension(&actual))
+        else {
+            return Ok(());
+        };
+        if wanted_type == actual_type {
+            return Ok(());
+        }
+
+        let message = format!(
+            "{} has a .{} extension, but the asset is {} (detected from the {})",
+            destination.display(),
+            wanted,
+            actual_type,
+            detected.source
+        );
+        if self.config.strict {
+            return Err(DownloadError::InvalidResponse(format!("{}. Rename the destination to .{}, or drop --strict to save it anyway", message, actual)));
+        }
+        eprintln!("Warning: {}", message);
+        Ok(())
+    }
+
+    /// The uploaded filename to save under instead of the asset ID. `--use-original-name` takes
+    /// it from Content-Disposition, where GitHub's signed redirect URL also carries it;
+    /// `--preserve-query-filename` only looks at the URL.
+    fn original_filename<'a>(&self, detected: Option<&'a DetectedType>) -> Option<&'a str> {
+        let detected = detected?;
+        if self.config.use_original_name {
+            return detected.disposition_filename.as_deref().or(detected.query_filename.as_deref());
+        }
+        if self.config.preserve_query_filename {
+            return detected.query_filename.as_deref();
+        }
+        None
+    }
+
+    /// Windows silently strips trailing dots and spaces from filenames, so the file would not land
+    /// where we report it. Returns the corrected path, or None if no trimming is needed.
+    fn trim_windows_filename(&self, path: &Path) -> Option<PathBuf> {
+        let filename = path.file_name()?.to_string_lossy();
+        let trimmed = filename.trim_end_matches(['.', ' ']);
+        if trimmed.len() == filename.len() || trimmed.is_empty() {
+            return None;
+        }
+        Some(path.with_file_name(trimmed))
+    }
+
+    /// Probes the asset with a HEAD request and collects every extension signal it offers.
+    async fn resolve_extension(&self, url: &str) -> Result<ExtensionResolution> {
+        let response = self.send_head(url).await?;
+        Ok(self.resolution_from_head(&response))
+    }
+
+    /// The HEAD probe, without following redirects: the redirect URL is itself a clue to the
+    /// type. Fails on statuses other than success and redirection.
+    async fn send_head(&self, url: &str) -> Result<reqwest::Response> {
+        let client = self.client()?;
+        let mut request = client
+            .head(url)
+            .header("Authorization", format!("token {}", self.auth.get_token()))
+            .timeout(self.head_timeout());
+        if let Some(accept) = self.accept_header(url) {
+            request = request.header("Accept", accept);
+        }
+        request = self.with_extra_headers(request);
+        let response = self
+            .send_traced(client, request)
+            .await
+            .map_err(|e| DownloadError::network("Failed to send HEAD request", e))?;
+
+        let status = response.status();
+        if !status.is_success() && !status.is_redirection() {
+            return Err(DownloadError::HttpStatus {
+                status: status.as_u16(),
+                message: format!("HEAD request failed with status: {}", status),
+            });
+        }
+        Ok(response)
+    }
+
+    fn resolution_from_head(&self, response: &reqwest::Response) -> ExtensionResolution {
+        let status = response.status();
+        let header = |name: &str| response.headers().get(name).and_then(|v| v.to_str().ok());
+        let location = if status.is_redirection() { header("location") } else { None };
+        let (disposition, content_type) = if status.is_success() {
+            (header("content-disposition"), header("content-type"))
+        } else {
+            (None, None)
+        };
+
+        self.resolve_extension_from_headers(location, disposition, content_type)
+    }
+
+    /// The asset's size from a HEAD response. GitHub answers with a redirect, which under
+    /// `ask_storage` is followed once, without the token, to ask the storage host. Storage URLs
+    /// signed for GET only may refuse HEAD, leaving the size unknown.
+    async fn head_size(&self, response: &reqwest::Response, ask_storage: bool) -> Option<u64> {
+        fn content_length(response: &reqwest::Response) -> Option<u64> {
+            response.headers().get("content-length")?.to_str().ok()?.parse().ok()
+        }
+        if response.status().is_success() {
+            return content_length(response);
+        }
+        if !ask_storage {
+            return None;
+        }
+        let location = response.url().join(response.headers().get("location")?.to_str().ok()?).ok()?;
+        let client = self.client().ok()?;
+        let request = client.head(location).timeout(self.head_timeout());
+        let redirected = self.send_traced(client, request).await.ok()?;
+        redirected.status().is_success().then(|| content_length(&redirected)).flatten()
+    }
+
+    /// Derives a candidate extension from each signal and picks one by precedence: the redirect
+    /// URL, then the Content-Disposition filename, then the Content-Type, then `.bin`.
+    fn resolve_extension_from_headers(
+        &self,
+        location: Option<&str>,
+        disposition: Option<&str>,
+        content_type: Option<&str>,
+    ) -> ExtensionResolution {
+        let mut considered = Vec::new();
+        if let Some(location) = location {
+            considered.push(("location", location.to_string()));
+        }
+        if let Some(disposition) = disposition {
+            considered.push(("content-disposition", disposition.to_string()));
+        }
+        if let Some(content_type) = content_type {
+            considered.push(("content-type", content_type.to_string()));
+        }
+
+        let mime_type = content_type
+            .map(|mime_type| mime_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase());
+        let from_url = location.and_then(|l| self.extract_extension_from_url(l));
+        let from_disposition = disposition
+            .and_then(|d| self.extract_filename_from_disposition(d))
+            .and_then(|filename| self.normalize_extension(&filename));
+        let from_content_type = mime_type
+            .as_deref()
+            .map(|mime_type| self.get_extension_from_mime_type(mime_type));
+
+        let (extension, source) = if let Some(extension) = &from_url {
+            (extension.clone(), TypeSource::Url)
+        } else if let Some(extension) = &from_disposition {
+            (extension.clone(), TypeSource::Disposition)
+        } else if let Some(extension) = &from_content_type {
+            (extension.clone(), TypeSource::ContentType)
+        } else {
+            (".bin".to_string(), TypeSource::Fallback)
+        };
+
+        ExtensionResolution {
+            from_url,
+            from_disposition,
+            from_content_type,
+            chosen: DetectedType {
+                extension,
+                mime_type,
+                source,
+                considered,
+                query_filename: location.and_then(|l| self.extract_filename_from_query(l)),
+                disposition_filename: disposition
+                    .and_then(|d| self.extract_filename_from_disposition(d))
+                    .and_then(|filename| self.sanitize_filename(&filename)),
+            },
+        }
+    }
+
+    fn print_extension_candidates(&self, asset_id: &str, resolution: &ExtensionResolution) {
+        if !self.config.verbose {
+            return;
+        }
+
+        let candidate = |extension: &Option<String>| extension.clone().unwrap_or_else(|| "none".to_string());
+        eprintln!(
+            "Extension candidates for {}: redirect URL {}, content-disposition {}, content-type {}",
+            asset_id,
+            candidate(&resolution.from_url),
+            candidate(&resolution.from_disposition),
+            candidate(&resolution.from_content_type)
+        );
+    }
+
+    /// Under `--verbose`, explains which signal decided the output name and what was considered.
+    fn print_naming_trace(&self, asset_id: &str, detected: Option<&DetectedType>, final_path: &Path) {
+        if !self.config.verbose {
+            return;
+        }
+
+        eprintln!("Naming {}:", asset_id);
+        match detected {
+            Some(detected) => {
+                for (name, value) in &detected.considered {
+                    eprintln!("  considered {}: {}", name, value);
+                }
+                let raw_content_type = detected.considered.iter().find(|(name, _)| *name == "content-type");
+                if let (Some((_, raw)), Some(normalized)) = (raw_content_type, &detected.mime_type) {
+                    if raw != normalized {
+                        eprintln!("  normalized content-type to {}", normalized);
+                    }
+                }
+                eprintln!("  chose extension {} from {}", detected.extension, detected.source);
+                if let Some(filename) = self.original_filename(Some(detected)) {
+                    eprintln!("  kept original filename {}", filename);
+                }
+            }
+            None => eprintln!("  using the destination filename as given"),
+        }
+        eprintln!("  output: {}", final_path.display());
+    }
+
+    /// Returns the type forced with `--content-type-override` for this asset, which takes
+    /// precedence over anything the server reports.
+    fn get_overridden_type(&self, asset_id: &str) -> Option<DetectedType> {
+        let mime_type = self.config.content_type_overrides.get(asset_id)?;
+        Some(DetectedType {
+            extension: self.get_extension_from_mime_type(mime_type),
+            mime_type: Some(mime_type.clone()),
+            source: TypeSource::Override,
+            considered: vec![("override", mime_type.clone())],
+            query_filename: None,
+            disposition_filename: None,
+        })
+    }
+
+    /// Checks a detected type against `--only-if-type`, which accepts extensions (`png`, `.mp4`),
+    /// exact MIME types (`image/png`) and MIME wildcards (`video/*`). An empty filter matches everything.
+    fn matches_type_filter(&self, extension: &str, mime_type: Option<&str>) -> bool {
+        if self.config.only_if_type.is_empty() {
+            return true;
+        }
+
+        let extension = extension.trim_start_matches('.').to_ascii_lowercase();
+        // Redirect-based detection only yields an extension, so derive the MIME type from it
+        let mime_type = mime_type
+            .map(str::to_string)
+            .or_else(|| self.get_mime_type_from_extension(&extension).map(str::to_string));
+
+        self.config.only_if_type.iter().any(|wanted| {
+            let wanted = wanted.trim().to_ascii_lowercase();
+            match wanted.split_once('/') {
+                Some((top_level, "*")) => mime_type
+                    .as_deref()
+                    .is_some_and(|m| m.split('/').next() == Some(top_level)),
+                Some(_) => mime_type.as_deref() == Some(wanted.as_str())
+                    || self.get_extension_from_mime_type(&wanted).trim_start_matches('.') == extension,
+                None => {
+                    let wanted = wanted.trim_start_matches('.');
+                    wanted == extension || extension.ends_with(&format!(".{}", wanted))
+                }
+            }
+        })
+    }
+
+    /// Identifies common file formats from their leading magic bytes.
+    fn sniff_mime_type(&self, bytes: &[u8]) -> Option<&'static str> {
+        let mime_type = match bytes {
+            [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, ..] => "image/png",
+            [0xFF, 0xD8, 0xFF, ..] => "image/jpeg",
+            [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => "image/gif",
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => "image/webp",
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => "audio/wav",
+            [b'I', b'I', b'*', 0x00, ..] | [b'M', b'M', 0x00, b'*', ..] => "image/tiff",
+            [b'B', b'M', ..] => "image/bmp",
+            [b'%', b'P', b'D', b'F', b'-', ..] => "application/pdf",
+            [b'P', b'K', 0x03, 0x04, ..] => "application/zip",
+            [0x1F, 0x8B, ..] => "application/gzip",
+            [_, _, _, _, b'f', b't', b'y', b'p', b'q', b't', b' ', b' ', ..] => "video/quicktime",
+            [_, _, _, _, b'f', b't', b'y', b'p', ..] => "video/mp4",
+            [0x00, 0x00, 0x01, 0xBA, ..] => "video/mpeg",
+            [b'I', b'D', b'3', ..] | [0xFF, 0xFB, ..] => "audio/mpeg",
+            [b'O', b'g', b'g', b'S', ..] => "audio/ogg",
+            _ => return None,
+        };
+        Some(mime_type)
+    }
+
+    /// Under `--verify-type`, confirms from the body's magic bytes that the asset really matches
+    /// `--only-if-type`, so a mislabeled Content-Type can't slip through the filter.
+    fn verify_content_type(&self, bytes: &[u8]) -> Result<(), String> {
+        if !self.config.verify_type {
+            return Ok(());
+        }
+
+        match self.sniff_mime_type(bytes) {
+            Some(mime_type) => {
+                let extension = self.get_extension_from_mime_type(mime_type);
+                if self.matches_type_filter(&extension, Some(mime_type)) {
+                    Ok(())
+                } else {
+                    Err(format!("content is actually {}", mime_type))
+                }
+            }
+            None => Err("content type could not be verified from its leading bytes".to_string()),
+        }
+    }
+
+    fn extract_extension_from_url(&self, url: &str) -> Option<String> {
+        let url_path = url.split(['?', '#']).next().unwrap_or(url);
+        let filename = &url_path[url_path.rfind('/')? + 1..];
+        self.normalize_extension(filename)
+    }
+
+    /// The extension of `filename`, lowercased and with `.` in front, if it's one gh-asset
+    /// knows: a [`MIME_TYPES`] extension, or one of [`COMPOUND_EXTENSIONS`] kept whole. Anything
+    /// after a `?` or `#` left over from a URL is cut off first. Unknown extensions give None,
+    /// so the next signal decides rather than a crafted name such as `shot.php`.
+    fn normalize_extension(&self, filename: &str) -> Option<String> {
+        let filename = filename.split(['?', '#']).next().unwrap_or(filename).to_ascii_lowercase();
+        if let Some(compound) = COMPOUND_EXTENSIONS.iter().find(|compound| filename.ends_with(&format!(".{}", compound))) {
+            return Some(format!(".{}", compound));
+        }
+        let (stem, extension) = filename.rsplit_once('.')?;
+        if stem.is_empty() || self.get_mime_type_from_extension(extension).is_none() {
+            return None;
+        }
+        Some(format!(".{}", extension))
+    }
+
+    /// S3 signed URLs often carry the original name as
+    /// `response-content-disposition=attachment%3B%20filename%3D...`.
+    fn extract_filename_from_query(&self, url: &str) -> Option<String> {
+        let parsed = url::Url::parse(url).ok()?;
+        let disposition = parsed
+            .query_pairs()
+            .find(|(name, _)| name.eq_ignore_ascii_case("response-content-disposition"))?
+            .1;
+        let filename = self.extract_filename_from_disposition(&disposition)?;
+        self.sanitize_filename(&filename)
+    }
+
+    /// Accepts a server-provided filename only if it is a plain name that can't escape the
+    /// destination directory.
+    fn sanitize_filename(&self, filename: &str) -> Option<String> {
+        let filename = filename.trim();
+        if filename.is_empty()
+            || filename == "."
+            || filename == ".."
+            || filename.contains(['/', '\\'])
+            || filename.chars().any(char::is_control)
+        {
+            return None;
+        }
+        Some(filename.to_string())
+    }
+
+    /// Prefers the RFC 5987 `filename*=UTF-8''my%20file.png` form, which GitHub uses for names
+    /// with spaces or non-ASCII characters, over the plain `filename=`.
+    fn extract_filename_from_disposition(&self, disposition: &str) -> Option<String> {
+        if let Some(filename) = self.extract_encoded_filename(disposition) {
+            return Some(filename);
+        }
+        if let Some(filename_start) = disposition.find("filename=") {
+            let filename_part = &disposition[filename_start + 9..];
+            if let Some(quoted) = filename_part.strip_prefix('"') {
+                if let Some(end_quote) = quoted.find('"') {
+                    return Some(quoted[..end_quote].to_string());
+                }
+            } else {
+                let filename = filename_part.split(';').next().unwrap_or("").trim();
+                if !filename.is_empty() {
+                    return Some(filename.to_string());
+                }
+            }
+        }
+        None
+    }
+
+    /// Decodes `filename*=<charset>'<language>'<percent-encoded name>`. Only UTF-8 and
+    /// ISO-8859-1, the two charsets RFC 5987 requires, are understood.
+    fn extract_encoded_filename(&self, disposition: &str) -> Option<String> {
+        let start = disposition.to_ascii_lowercase().find("filename*=")?;
+        let value = disposition[start + 10..].split(';').next()?.trim().trim_matches('"');
+        let mut parts = value.splitn(3, '\'');
+        let (charset, _language, encoded) = (parts.next()?, parts.next()?, parts.next()?);
+
+        let mut bytes = Vec::with_capacity(encoded.len());
+        let mut rest = encoded.as_bytes();
+        while let Some((&byte, tail)) = rest.split_first() {
+            if byte == b'%' {
+                let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+                rest = &tail[2..];
+            } else {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+
+        let filename = if charset.eq_ignore_ascii_case("utf-8") {
+            String::from_utf8(bytes).ok()?
+        } else if charset.eq_ignore_ascii_case("iso-8859-1") {
+            bytes.into_iter().map(char::from).collect()
+        } else {
+            return None;
+        };
+        (!filename.is_empty()).then_some(filename)
+    }
+
+    /// Looks the MIME type up in [`MIME_TYPES`]. Other image, video and audio types get their
+    /// subtype as the extension (`image/jxl` → `.jxl`) when it is a plain short name; anything
+    /// else falls back to `.bin`.
+    fn get_extension_from_mime_type(&self, mime_type: &str) -> String {
+        if let Some((_, extension)) = MIME_TYPES.iter().find(|(known, _)| *known == mime_type) {
+            return format!(".{}", extension);
+        }
+
+        let generic = mime_type
+            .split_once('/')
+            .filter(|(top, _)| matches!(*top, "image" | "video" | "audio"))
+            .map(|(_, subtype)| subtype.trim_start_matches("x-"))
+            .filter(|subtype| {
+                (2..=5).contains(&subtype.len()) && subtype.chars().all(|c| c.is_ascii_alphanumeric())
+            });
+        match generic {
+            Some(subtype) => format!(".{}", subtype),
+            None => ".bin".to_string(),
+        }
+    }
+
+    /// The first [`MIME_TYPES`] entry with this extension gives its MIME type. A compound
+    /// extension has the type of its last part, so `tar.gz` is `application/gzip`.
+    fn get_mime_type_from_extension(&self, extension: &str) -> Option<&str> {
+        let extension = extension.rsplit('.').next().unwrap_or(extension);
+        MIME_TYPES
+            .iter()
+            .find(|(_, known)| *known == extension)
+            .map(|(mime_type, _)| *mime_type)
+    }
+
+    /// `auto_named` marks a destination whose filename was generated in directory mode, and so
+    /// may have its extension corrected from the body.
+    /// `type_pending` means the HEAD probe failed, so `--only-if-type` and the extension of an
+    /// auto-named file are worked out from the GET response's headers instead.
+    async fn download_with_reqwest(
+        &self,
+        asset_id: &str,
+        url: &str,
+        destination: &Path,
+        auto_named: bool,
+        type_pending: bool,
+    ) -> Result<Option<DownloadOutcome>> {
+        self.status(format_args!("Downloading {} to {}", url, destination.display()));
+
+        // Timeouts are retried separately, allowing more time on each attempt. Other transient
+        // failures are retried under --retries after a backoff.
+        let mut attempt = 0;
+        let mut transient_retries = 0;
+        let recorded = if auto_named { None } else { self.recorded_validators(asset_id, destination) };
+        loop {
+            let attempts = attempt + transient_retries + 1;
+            let timeout = self.timeout_for_attempt(attempt);
+            let resume_from = self.resume_offset(destination);
+            let (response, chain) = match self.get_following_redirects(url, timeout, resume_from, recorded.as_ref()).await {
+                Ok(redirected) => redirected,
+                Err(DownloadError::Network { error, .. }) => {
+                    if self.should_retry_timeout(&error, attempt) {
+                        attempt += 1;
+                        continue;
+                    }
+                    if self.is_transient_error(&error) {
+                        if let Some(delay) = self.transient_retry_delay(&error.to_string(), transient_retries, None) {
+                            tokio::time::sleep(delay).await;
+                            transient_retries += 1;
+                            continue;
+                        }
+                    }
+                    let context = format!("Failed to send HTTP request after {}", attempts_label(attempts));
+                    return Err(DownloadError::network(context, error));
+                }
+                Err(e) => return Err(e),
+            };
+
+            // A partial file that is already complete, or longer than the asset, can't be resumed
+            let status = response.status();
+            if resume_from.is_some() && status == reqwest::StatusCode::RANGE_NOT_SATISFIABLE {
+                self.status(format_args!("Cannot resume {}, downloading it from the start", destination.display()));
+                let _ = std::fs::remove_file(AssetSink::temp_path(destination));
+                continue;
+            }
+            if recorded.is_some() && status == reqwest::StatusCode::NOT_MODIFIED {
+                self.status(format_args!("{} is not modified since it was downloaded, skipping", destination.display()));
+                return Ok(None);
+            }
+
+            // Check response status. Server errors and rate limiting may clear up; other
+            // failures such as 404 won't, so they are reported straight away.
+            if !status.is_success() {
+                if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+                    let retry_after = response
+                        .headers()
+                        .get("retry-after")
+                        .and_then(|v| v.to_str().ok())
+                        .and_then(|v| self.parse_retry_after(v));
+                    if let Some(delay) = self.transient_retry_delay(&format!("HTTP {}", status), transient_retries, retry_after) {
+                        tokio::time::sleep(delay).await;
+                        transient_retries += 1;
+                        continue;
+                    }
+                }
+                let mut message = format!(
+                    "HTTP request failed with status: {} - {} (after {})",
+                    status,
+                    status.canonical_reason().unwrap_or("Unknown error"),
+                    attempts_label(attempts)
+                );
+                if let Some(snippet) = self.read_error_snippet(response).await {
+                    message = format!("{}: {}", message, snippet);
+                }
+                // A 403 from the storage host rather than GitHub usually means the signed URL expired
+                if chain.redirected() {
+                    message = format!("{} (redirects: {})", message, chain);
+                }
+                return Err(DownloadError::HttpStatus { status: status.as_u16(), message });
+            }
+
+            let header = |name: &str| response.headers().get(name).and_then(|v| v.to_str().ok());
+            let detected = type_pending.then(|| {
+                self.resolve_extension_from_headers(Some(response.url().as_str()), header("content-disposition"), header("content-type"))
+                    .chosen
+            });
+            if let Some(detected) = &detected {
+                if !self.matches_type_filter(&detected.extension, detected.mime_type.as_deref()) {
+                    self.status(format_args!("Skipping {}: detected type {} does not match --only-if-type", asset_id, detected.extension));
+                    return Ok(None);
+                }
+            }
+            if !auto_named && !self.is_stream(destination) {
+                let detected = self.resolve_extension_from_headers(
+                    Some(response.url().as_str()),
+                    header("content-disposition"),
+                    header("content-type"),
+                );
+                self.check_extension_matches(destination, &detected.chosen)?;
+            }
+
+            let source = AssetSource {
+                asset_id: asset_id.to_string(),
+                source_url: url.to_string(),
+                final_url: Some(response.url().to_string()),
+                content_type: response
+                    .headers()
+                    .get("content-type")
+                    .and_then(|v| v.to_str().ok())
+                    .map(str::to_string),
+            };
+            // Only append once Content-Range confirms the server started where the file ends. A
+            // 200 is the full body, which simply overwrites the partial file.
+            let resumed = match resume_from {
+                Some(offset) if status == reqwest::StatusCode::PARTIAL_CONTENT => {
+                    let start = response
+                        .headers()
+                        .get("content-range")
+                        .and_then(|v| v.to_str().ok())
+                        .and_then(|v| self.content_range_start(v));
+                    if start != Some(offset) {
+                        self.status(format_args!("Server resumed {} at the wrong offset, downloading it from the start", destination.display()));
+                        let _ = std::fs::remove_file(AssetSink::temp_path(destination));
+                        continue;
+                    }
+                    self.status(format_args!("Resuming {} from byte {}", destination.display(), offset));
+                    Some(offset)
+                }
+                Some(_) => {
+                    self.status(format_args!("Server ignored the range request, downloading {} from the start", destination.display()));
+                    None
+                }
+                None => None,
+            };
+            let content_length = response.content_length().map(|len| len + resumed.unwrap_or(0));
+            if let Err(e) = content_length.map_or(Ok(()), |len| self.check_size(len)) {
+                let _ = std::fs::remove_file(AssetSink::temp_path(destination));
+                return Err(e);
+            }
+            let mut sink = AssetSink::new(self, destination, auto_named, source, content_length);
+            if let Some(detected) = detected.as_ref().filter(|_| auto_named) {
+                sink.apply_header_extension(detected);
+            }
+            sink.validators = Validators::from_headers(header("etag"), header("last-modified"));
+            if let Some(offset) = resumed {
+                sink.resume(offset)?;
+            }
+
+            // Stream the body to disk chunk by chunk instead of buffering it all in memory
+            let mut stream = response.bytes_stream();
+            let mut throttle = self.config.limit_rate.map(Throttle::new);
+            let streamed = loop {
+                match stream.next().await {
+                    Some(Ok(chunk)) => match sink.push(&chunk) {
+                        Ok(true) => {
+                            if let Some(pause) = throttle.as_mut().and_then(|t| t.pause(chunk.len())) {
+                                tokio::time::sleep(pause).await;
+                            }
+                        }
+                        Ok(false) => break Ok(()),
+                        // Not worth resuming, so the partial file goes even under --resume
+                        Err(e @ DownloadError::TooLarge { .. }) => {
+                            sink.discard();
+                            return Err(e);
+                        }
+                        Err(e) => {
+                            sink.abandon();
+                            return Err(e);
+                        }
+                    },
+                    Some(Err(e)) => break Err(e),
+                    None => break Ok(()),
+                }
+            };
+
+            match streamed {
+                Ok(()) if sink.received == 0 && !self.config.allow_empty => {
+                    sink.discard();
+                    return Err(self.empty_asset_error());
+                }
+                Ok(()) => return sink.finish(),
+                Err(e) => {
+                    let can_retry = sink.can_retry();
+                    sink.abandon();
+                    if can_retry && self.should_retry_timeout(&e, attempt) {
+                        attempt += 1;
+                        continue;
+                    }
+                    if can_retry && self.is_transient_error(&e) {
+                        if let Some(delay) = self.transient_retry_delay(&e.to_string(), transient_retries, None) {
+                            tokio::time::sleep(delay).await;
+                            transient_retries += 1;
+                            continue;
+                        }
+                    }
+                    let context = format!("Failed to read response body after {}", attempts_label(attempts));
+                    return Err(DownloadError::network(context, e));
+                }
+            }
+        }
+    }
+
+    /// Reads the start of an error response's body. GitHub and S3 explain failures such as an
+    /// expired signature or a token without access there, where the status alone doesn't.
+    async fn read_error_snippet(&self, response: reqwest::Response) -> Option<String> {
+        let mut stream = response.bytes_stream();
+        let mut body = Vec::new();
+        while body.len() < ERROR_BODY_READ_LIMIT {
+            match stream.next().await {
+                Some(Ok(chunk)) => body.extend_from_slice(&chunk),
+                _ => break,
+            }
+        }
+        self.error_snippet(&String::from_utf8_lossy(&body))
+    }
+
+    /// Collapses whitespace so XML and HTML bodies fit on one line, and truncates to
+    /// [`ERROR_SNIPPET_LEN`] characters.
+    fn error_snippet(&self, body: &str) -> Option<String> {
+        let collapsed = body.split_whitespace().collect::<Vec<_>>().join(" ");
+        if collapsed.is_empty() {
+            return None;
+        }
+        match collapsed.char_indices().nth(ERROR_SNIPPET_LEN) {
+            Some((end, _)) => Some(format!("{}...", &collapsed[..end])),
+            None => Some(collapsed),
+        }
+    }
+
+    /// A 200 with no body usually means something went wrong upstream rather than a genuinely
+    /// empty upload, so it isn't saved as if it were the asset.
+    fn empty_asset_error(&self) -> DownloadError {
+        DownloadError::InvalidResponse("Downloaded asset was empty. Pass --allow-empty to save it anyway".to_string())
+    }
+
+    /// Fails once the bytes of an asset, announced or received so far, exceed `--max-size`.
+    fn check_size(&self, size: u64) -> Result<()> {
+        match self.config.max_size {
+            Some(limit) if size > limit => Err(DownloadError::TooLarge { limit }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Sends the GET, following up to 10 redirects. The token is only sent to the origin (scheme,
+    /// host and port) of the original URL, never to the storage host GitHub redirects to, nor
+    /// over a redirect that downgrades to plain HTTP. Also returns the hops taken, so a failure
+    /// can show where it happened.
+    async fn get_following_redirects(
+        &self,
+        url: &str,
+        timeout: Option<std::time::Duration>,
+        range_start: Option<u64>,
+        conditional: Option<&Validators>,
+    ) -> Result<(reqwest::Response, RedirectChain)> {
+        let client = self.client()?;
+        let origin = reqwest::Url::parse(url).map_err(|e| DownloadError::InvalidAssetId(format!("Invalid asset URL: {}", e)))?;
+        let mut current = origin.clone();
+        let mut chain = RedirectChain::default();
+
+        for _ in 0..=MAX_REDIRECTS {
+            let mut request = client.get(current.clone());
+            if let Some(accept) = self.accept_header(origin.as_str()) {
+                request = request.header("Accept", accept);
+            }
+            if let Some(timeout) = timeout {
+                request = request.timeout(timeout);
+            }
+            if let Some(start) = range_start {
+                request = request.header("Range", format!("bytes={}-", start));
+            }
+            // GitHub just redirects either way; it is the storage host that can answer 304
+            if let Some(validators) = conditional {
+                if let Some(etag) = &validators.etag {
+                    request = request.header("If-None-Match", etag);
+                }
+                if let Some(last_modified) = &validators.last_modified {
+                    request = request.header("If-Modified-Since", last_modified);
+                }
+            }
+            if current.origin() == origin.origin() {
+                request = request.header("Authorization", format!("token {}", self.auth.get_token()));
+                request = self.with_extra_headers(request);
+            }
+            let response = self
+                .send_traced(client, request)
+                .await
+                .map_err(|e| DownloadError::network("Failed to send HTTP request", e))?;
+            chain.record(&current, response.status());
+
+            let location = response
+                .headers()
+                .get("location")
+                .and_then(|v| v.to_str().ok())
+                .filter(|_| response.status().is_redirection());
+            match location {
+                Some(location) => {
+                    current = current
+                        .join(location)
+                        .map_err(|e| DownloadError::InvalidResponse(format!("Invalid redirect location '{}': {}", location, e)))?;
+                    log::debug!("Redirected to {}", redact_url(&current));
+                }
+                None => return Ok((response, chain)),
+            }
+        }
+        Err(DownloadError::InvalidResponse(format!("Too many redirects while downloading {}: {}", url, chain)))
+    }
+
+    /// The GET timeout grows by the base `--timeout` with every timeout retry. None unless
+    /// `--timeout` asks for one: a large asset on a slow connection may legitimately take longer
+    /// than any fixed limit, and the stall timeout already catches transfers that stop.
+    fn timeout_for_attempt(&self, attempt: u32) -> Option<std::time::Duration> {
+        match self.config.timeout.unwrap_or(0) {
+            0 => None,
+            secs => Some(std::time::Duration::from_secs(secs) * (attempt + 1)),
+        }
+    }
+
+    /// The HEAD probe has no body to wait for, so it gets 30s, or less when `--timeout` is lower.
+    fn head_timeout(&self) -> std::time::Duration {
+        let head = std::time::Duration::from_secs(HEAD_TIMEOUT_SECS);
+        match self.timeout_for_attempt(0) {
+            Some(timeout) => timeout.min(head),
+            None => head,
+        }
+    }
+
+    /// Under `--resume`, the length of a partial download left behind by an earlier attempt.
+    fn resume_offset(&self, destination: &Path) -> Option<u64> {
+        if !self.config.resume || self.is_stream(destination) {
+            return None;
+        }
+        std::fs::metadata(AssetSink::temp_path(destination))
+            .ok()
+            .map(|metadata| metadata.len())
+            .filter(|len| *len > 0)
+    }
+
+    /// The first byte position of a `Content-Range: bytes <start>-<end>/<total>` header.
+    fn content_range_start(&self, value: &str) -> Option<u64> {
+        let range = value.trim().strip_prefix("bytes ")?;
+        let (start, _) = range.split_once('-')?;
+        start.trim().parse().ok()
+    }
+
+    /// Sends a request, recording the exchange to `--trace-file` when one is configured.
+    /// Every request is also logged at debug level, and its headers at trace level.
+    async fn send_traced(&self, client: &reqwest::Client, request: reqwest::RequestBuilder) -> reqwest::Result<reqwest::Response> {
+        let request = request.build()?;
+        let method = request.method().to_string();
+        let logged_url = redact_url(request.url());
+        let request_headers = redact_headers(request.headers());
+        log::trace!("{} {} request headers: {:?}", method, logged_url, request_headers);
+
+        let url = request.url().to_string();
+        let started = std::time::Instant::now();
+        let result = client.execute(request).await;
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+
+        match &result {
+            Ok(response) => {
+                log::debug!("{} {} -> {} ({}ms)", method, logged_url, response.status(), elapsed_ms);
+                log::trace!("{} {} response headers: {:?}", method, logged_url, redact_headers(response.headers()));
+            }
+            // reqwest's own message repeats the unredacted URL, so only its cause is logged
+            Err(e) => log::debug!(
+                "{} {} failed after {}ms: {}",
+                method,
+                logged_url,
+                elapsed_ms,
+                std::error::Error::source(e).map_or_else(|| "request failed".to_string(), |cause| cause.to_string())
+            ),
+        }
+
+        let Some(trace) = &self.config.trace_file else {
+            return result;
+        };
+        match &result {
+            Ok(response) => trace.record(serde_json::json!({
+                "event": "http",
+                "method": method,
+                "url": url,
+                "request_headers": request_headers,
+                "status": response.status().as_u16(),
+                "response_headers": redact_headers(response.headers()),
+                "elapsed_ms": elapsed_ms,
+            })),
+            Err(e) => trace.record(serde_json::json!({
+                "event": "http_error",
+                "method": method,
+                "url": url,
+                "request_headers": request_headers,
+                "error": e.to_string(),
+                "timeout": e.is_timeout(),
+                "elapsed_ms": elapsed_ms,
+            })),
+        }
+        result
+    }
+
+    /// Only timeouts are retried, and only up to `--timeout-retries` times.
+    fn should_retry_timeout(&self, error: &reqwest::Error, attempt: u32) -> bool {
+        let retry = error.is_timeout() && attempt < self.config.timeout_retries;
+        if let Some(trace) = &self.config.trace_file {
+            trace.record(serde_json::json!({
+                "event": "retry_decision",
+                "error": error.to_string(),
+                "timeout": error.is_timeout(),
+                "attempt": attempt + 1,
+                "retry": retry,
+            }));
+        }
+        if !retry {
+            return false;
+        }
+        match (self.timeout_for_attempt(attempt), self.timeout_for_attempt(attempt + 1)) {
+            (Some(current), Some(next)) => eprintln!(
+                "Request timed out after {}s, retrying with a {}s timeout ({}/{})",
+                current.as_secs(),
+                next.as_secs(),
+                attempt + 1,
+                self.config.timeout_retries
+            ),
+            // Without --timeout, only the connection or a stall can have timed out
+            _ => eprintln!("Request stalled, retrying ({}/{})", attempt + 1, self.config.timeout_retries),
+        }
+        true
+    }
+
+    /// Connection failures and dropped bodies, such as a reset from S3. Timeouts are left to
+    /// `--timeout-retries`.
+    fn is_transient_error(&self, error: &reqwest::Error) -> bool {
+        !error.is_timeout() && (error.is_connect() || error.is_request() || error.is_body())
+    }
+
+    /// Decides whether a transient failure gets another attempt under `--retries`, and if so how
+    /// long to wait first: as long as the server's Retry-After asks, or the usual backoff.
+    fn transient_retry_delay(
+        &self,
+        reason: &str,
+        retry: u32,
+        retry_after: Option<std::time::Duration>,
+    ) -> Option<std::time::Duration> {
+        let delay = (retry < self.config.retries).then(|| retry_after.unwrap_or_else(|| self.backoff_delay(retry)));
+        if let Some(trace) = &self.config.trace_file {
+            trace.record(serde_json::json!({
+                "event": "retry_decision",
+                "error": reason,
+                "timeout": false,
+                "attempt": retry + 1,
+                "retry": delay.is_some(),
+                "delay_ms": delay.map(|d| d.as_millis() as u64),
+                "retry_after": retry_after.is_some(),
+            }));
+        }
+        if let (Some(delay), Some(_)) = (delay, retry_after) {
+            eprintln!(
+                "{}, waiting {:.1}s as the server's Retry-After asks before retrying ({}/{})",
+                reason,
+                delay.as_secs_f64(),
+                retry + 1,
+                self.config.retries
+            );
+        } else if let Some(delay) = delay {
+            eprintln!(
+                "{}, retrying in {:.1}s ({}/{})",
+                reason,
+                delay.as_secs_f64(),
+                retry + 1,
+                self.config.retries
+            );
+        }
+        delay
+    }
+
+    /// Reads a Retry-After value, either delay-seconds or an HTTP date, as sent with `429` and
+    /// `503`. A date in the past means retry now; anything over 5 minutes is cut down to that.
+    fn parse_retry_after(&self, value: &str) -> Option<std::time::Duration> {
+        let value = value.trim();
+        let delay = match value.parse::<u64>() {
+            Ok(seconds) => std::time::Duration::from_secs(seconds),
+            Err(_) => {
+                let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+                (date.with_timezone(&chrono::Utc) - chrono::Utc::now()).to_std().unwrap_or_default()
+            }
+        };
+        Some(delay.min(std::time::Duration::from_secs(MAX_RETRY_AFTER_SECS)))
+    }
+
+    /// Exponential backoff from 500ms, capped at 30s, with up to 50% random jitter added so
+    /// concurrent downloads don't retry in lockstep.
+    fn backoff_delay(&self, retry: u32) -> std::time::Duration {
+        use std::hash::{BuildHasher, Hasher};
+
+        let base_ms = 500u64.saturating_mul(1 << retry.min(16)).min(30_000);
+        let random = std::collections::hash_map::RandomState::new().build_hasher().finish();
+        std::time::Duration::from_millis(base_ms + random % (base_ms / 2 + 1))
+    }
+
+    async fn download_with_gh(&self, url: &str, destination: &Path, asset_id: &str) -> Result<Option<DownloadOutcome>> {
+        self.status(format_args!("Downloading {} via gh api", url));
+
+        // gh follows the redirect itself and only prints the final response's headers
+        let mut args = vec!["api".to_string(), "--hostname".to_string(), self.host().to_string(), "--include".to_string()];
+        if let Some(accept) = self.accept_header(url) {
+            args.extend(["-H".to_string(), format!("Accept: {}", accept)]);
+        }
+        for (name, value) in self.extra_headers() {
+            args.extend(["-H".to_string(), format!("{}: {}", name, value)]);
+        }
+        if let Some(user_agent) = &self.config.user_agent {
+            args.extend(["-H".to_string(), format!("User-Agent: {}", user_agent)]);
+        }
+        args.push(url.to_string());
+
+        let started = std::time::Instant::now();
+        let mut command = tokio::process::Command::new("gh");
+        if let Some(proxy) = &self.config.proxy {
+            command.env("HTTPS_PROXY", proxy);
+        }
+        let output = command
+            .args(&args)
+            .kill_on_drop(true)
+            .output()
+            .await
+            .map_err(|e| {
+                DownloadError::GhCli(format!(
+                    "Failed to execute gh command: {}. Make sure GitHub CLI is installed and authenticated.",
+                    e
+                ))
+            })?;
+
+        if let Some(trace) = &self.config.trace_file {
+            trace.record(serde_json::json!({
+                "event": "command",
+                "program": "gh",
+                "args": args,
+                "exit_code": output.status.code(),
+                "stderr": String::from_utf8_lossy(&output.stderr),
+                "elapsed_ms": started.elapsed().as_millis() as u64,
+            }));
+        }
+
+        if !output.status.success() {
+            let error_msg = String::from_utf8_lossy(&output.stderr);
+            return Err(DownloadError::GhCli(format!("gh api request failed: {}", error_msg.trim())));
+        }
+
+        let (headers, body) = self.split_gh_include_output(&output.stdout)
+            .ok_or_else(|| DownloadError::GhCli("Failed to parse gh api response headers".to_string()))?;
+
+        let disposition = headers.get("content-disposition").map(String::as_str);
+        let content_type = headers.get("content-type").map(String::as_str);
+        let detected = self.get_overridden_type(asset_id)
+            .unwrap_or_else(|| self.resolve_extension_from_headers(None, disposition, content_type).chosen);
+
+        if !self.matches_type_filter(&detected.extension, detected.mime_type.as_deref()) {
+            self.status(format_args!("Skipping {}: detected type {} does not match --only-if-type", asset_id, detected.extension));
+            return Ok(None);
+        }
+
+        let final_path = if self.is_stdout(destination) {
+            destination.to_path_buf()
+        } else {
+            self.resolve_final_path(destination, asset_id, Some(&detected))
+        };
+        if self.skip_missing_for_refresh(asset_id, &final_path) {
+            return Ok(None);
+        }
+        if !destination.is_dir() && !self.is_stream(destination) {
+            self.check_extension_matches(destination, &detected)?;
+        }
+        self.print_naming_trace(asset_id, destination.is_dir().then_some(&detected), &final_path);
+
+        // gh only reports the final response's headers, not the URL it was redirected to
+        let source = AssetSource {
+            asset_id: asset_id.to_string(),
+            source_url: url.to_string(),
+            final_url: None,
+            content_type: content_type.map(str::to_string),
+        };
+        self.check_size(body.len() as u64)?;
+        if body.is_empty() && !self.config.allow_empty {
+            return Err(self.empty_asset_error());
+        }
+        let auto_named = destination.is_dir() && !self.is_stdout(destination);
+        let mut sink = AssetSink::new(self, &final_path, auto_named, source, Some(body.len() as u64));
+        // The transfer happened inside gh, so time it from when gh was started
+        sink.started = started;
+        sink.validators = Validators::from_headers(
+            headers.get("etag").map(String::as_str),
+            headers.get("last-modified").map(String::as_str),
+        );
+        if let Err(e) = sink.push(body) {
+            sink.abandon();
+            return Err(e);
+        }
+        sink.finish()
+    }
+
+    /// Splits `gh api --include` output into lowercased response headers and the raw body.
+    fn split_gh_include_output<'a>(&self, output: &'a [u8]) -> Option<(HashMap<String, String>, &'a [u8])> {
+        let (header_end, separator_len) = output
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .map(|pos| (pos, 4))
+            .or_else(|| output.windows(2).position(|w| w == b"\n\n").map(|pos| (pos, 2)))?;
+
+        let header_block = String::from_utf8_lossy(&output[..header_end]);
+        let mut lines = header_block.lines();
+        if !lines.next()?.starts_with("HTTP/") {
+            return None;
+        }
+
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+            .collect();
+
+        Some((headers, &output[header_end + separator_len..]))
+    }
+
+    /// GitHub answers some auth failures with a 200 HTML login or error page. Applies `--on-html`
+    /// when such a page arrives; returns whether the body should still be written.
+    fn check_html_response(&self, content_type: Option<&str>, bytes: &[u8], destination: &Path) -> Result<bool> {
+        if !self.looks_like_html(content_type, bytes) {
+            return Ok(true);
+        }
+
+        let expected_binary = self.expects_binary(destination);
+        let policy = self.config.on_html.unwrap_or(if expected_binary { OnHtml::Error } else { OnHtml::Save });
+        match policy {
+            OnHtml::Save => {
+                if expected_binary {
+                    eprintln!(
+                        "Warning: {} looks like an HTML login or error page rather than the asset; try re-authenticating with 'gh auth login'",
+                        destination.display()
+                    );
+                }
+                Ok(true)
+            }
+            OnHtml::Skip => {
+                self.status(format_args!("Skipping {}: received an HTML page instead of the asset", destination.display()));
+                Ok(false)
+            }
+            OnHtml::Error => Err(DownloadError::InvalidResponse(format!(
+                "Received an HTML page instead of the asset for {}. GitHub may be showing a login or error page; try re-authenticating with 'gh auth login', or pass --on-html save to keep it",
+                destination.display()
+            ))),
+        }
+    }
+
+    fn looks_like_html(&self, content_type: Option<&str>, bytes: &[u8]) -> bool {
+        let is_html_type = content_type
+            .and_then(|c| c.split(';').next())
+            .is_some_and(|mime_type| mime_type.trim().eq_ignore_ascii_case("text/html"));
+        if is_html_type {
+            return true;
+        }
+
+        let prefix = String::from_utf8_lossy(&bytes[..bytes.len().min(512)]).trim_start().to_ascii_lowercase();
+        prefix.starts_with("<!doctype html") || prefix.starts_with("<html")
+    }
+
+    /// Whether the destination's extension names a binary format that an HTML page can't be.
+    fn expects_binary(&self, destination: &Path) -> bool {
+        let extension = destination
+            .extension()
+            .map(|e| e.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        self.get_mime_type_from_extension(&extension)
+            .is_some_and(|mime_type| !mime_type.starts_with("text/") && mime_type != "application/json" && mime_type != "application/xml")
+    }
+
+    /// Fails before anything is written when `--convert` can't apply: the body must be an image
+    /// format that can be decoded, going to a regular file.
+    fn check_convertible(&self, source: &AssetSource, prefix: &[u8], destination: &Path) -> Result<()> {
+        if self.is_stream(destination) {
+            return Err(DownloadError::Convert("--convert needs a file destination, not stdout or a pipe".to_string()));
+        }
+        match image::guess_format(prefix) {
+            Ok(format) if format.reading_enabled() => Ok(()),
+            _ => {
+                let content_type = source.content_type.as_deref().unwrap_or("of unknown type");
+                let reason = match content_type.starts_with("image/") {
+                    true => format!("{} images can't be decoded; supported formats are {}", content_type, ConvertFormat::SUPPORTED),
+                    false => format!("it is not an image ({})", content_type),
+                };
+                Err(DownloadError::Convert(format!("Can't convert {}: {}", source.asset_id, reason)))
+            }
+        }
+    }
+
+    /// Re-encodes a finished download for `--convert`, saving it next to the original with the
+    /// target format's extension. Returns the converted file's path.
+    fn convert_image(&self, path: &Path, format: ConvertFormat) -> Result<PathBuf> {
+        let target = path.with_extension(format.extension());
+        let reader = image::ImageReader::open(path)
+            .and_then(|reader| reader.with_guessed_format())
+            .map_err(|e| DownloadError::io("Failed to open the download for conversion", e))?;
+        if target == path && reader.format() == Some(format.0) {
+            return Ok(target);
+        }
+        if target != path && !self.check_existing(&target)? {
+            return Ok(path.to_path_buf());
+        }
+
+        let decoded = reader
+            .decode()
+            .map_err(|e| DownloadError::Convert(format!("Failed to decode {}: {}", path.display(), e)))?;
+        // JPEG has no alpha channel
+        let decoded = match format.0 {
+            image::ImageFormat::Jpeg => image::DynamicImage::ImageRgb8(decoded.to_rgb8()),
+            _ => decoded,
+        };
+        let temp_path = AssetSink::temp_path(&target);
+        if let Err(e) = decoded.save_with_format(&temp_path, format.0) {
+            let _ = std::fs::remove_file(&temp_path);
+            return Err(DownloadError::Convert(format!("Failed to encode {} as {}: {}", path.display(), format.extension(), e)));
+        }
+        std::fs::rename(&temp_path, &target).map_err(|e| DownloadError::io("Failed to move the converted image into place", e))?;
+        if target != path && !self.config.keep_original {
+            std::fs::remove_file(path).map_err(|e| DownloadError::io("Failed to remove the original after converting it", e))?;
+        }
+        self.status(format_args!("Converted {} to {}", path.display(), target.display()));
+        Ok(target)
+    }
+
+    /// Under `--dedupe`, replaces `path` with a link to the first file of this run in the same
+    /// directory with the same content, or records it as that first file. The link is made under
+    /// a temporary name and renamed over `path`, so a failure leaves the downloaded copy in place.
+    fn link_duplicate(&self, path: &Path, sha256: &str) -> Result<()> {
+        let dir = path.parent().unwrap_or(Path::new(".")).to_path_buf();
+        let mut index = self.dedupe_index.lock().unwrap();
+        let first = match index.first_copies.get(&(dir.clone(), sha256.to_string())) {
+            Some(first) if first != path && first.exists() => first.clone(),
+            _ => {
+                index.first_copies.insert((dir, sha256.to_string()), path.to_path_buf());
+                return Ok(());
+            }
+        };
+
+        let temp_path = AssetSink::temp_path(path);
+        let _ = std::fs::remove_file(&temp_path);
+        let linked = std::fs::hard_link(&first, &temp_path);
+        // Both files are in the same directory, so the link can name its target directly
+        #[cfg(unix)]
+        let linked = linked.or_else(|_| std::os::unix::fs::symlink(first.file_name().unwrap_or_default(), &temp_path));
+        if let Err(e) = linked.and_then(|()| std::fs::rename(&temp_path, path)) {
+            let _ = std::fs::remove_file(&temp_path);
+            eprintln!("Warning: could not link {} to {}, keeping the copy: {}", path.display(), first.display(), e);
+            return Ok(());
+        }
+        index.collapsed += 1;
+        self.status(format_args!("{} is identical to {}, linked instead of kept as a copy", path.display(), first.display()));
+        Ok(())
+    }
+
+    /// Writes `<filename>.json` next to a finished download, recording where it came from.
+    fn write_metadata_sidecar(&self, destination: &Path, stats: &DownloadStats) -> Result<()> {
+        let sidecar_path = self.sidecar_path(destination);
+        let json = serde_json::to_vec_pretty(stats)
+            .map_err(|e| DownloadError::io("Failed to serialize download metadata", e.into()))?;
+        self.write_to_file(&sidecar_path, &json)
+    }
+
+    fn sidecar_path(&self, destination: &Path) -> PathBuf {
+        let mut sidecar_name = destination.file_name().unwrap_or_default().to_os_string();
+        sidecar_name.push(".json");
+        destination.with_file_name(sidecar_name)
+    }
+
+    /// Under `--if-modified-since`, the validators recorded when `destination` was last
+    /// downloaded. Only trusted while the sidecar is for the same asset and the file still has
+    /// the recorded size, so a truncated or replaced file is fetched again.
+    fn recorded_validators(&self, asset_id: &str, destination: &Path) -> Option<Validators> {
+        if !self.config.if_modified_since {
+            return None;
+        }
+        let size = std::fs::metadata(destination).ok().filter(|m| m.is_file())?.len();
+        let sidecar = std::fs::read(self.sidecar_path(destination)).ok()?;
+        let recorded: RecordedDownload = serde_json::from_slice(&sidecar).ok()?;
+        (recorded.asset_id == asset_id && recorded.size == size && !recorded.validators.is_empty())
+            .then_some(recorded.validators)
+    }
+
+    /// Decides whether an existing file at the final path may be replaced. By default that's an
+    /// error; `--overwrite` replaces it, `--no-clobber` quietly skips the asset, and
+    /// `--prompt-on-overwrite` asks (failing like the default when there is no terminal to ask on).
+    /// `--replace-existing-only` exists to replace files, so it always may.
+    fn check_existing(&self, destination: &Path) -> Result<bool> {
+        if !destination.exists() || self.config.overwrite || self.config.replace_existing_only {
+            return Ok(true);
+        }
+
+        if self.config.no_clobber {
+            if self.config.verbose {
+                eprintln!("Skipping {}: already exists (--no-clobber)", destination.display());
+            }
+            return Ok(false);
+        }
+
+        if self.config.prompt_on_overwrite {
+            if !std::io::stdout().is_terminal() {
+                return Err(DownloadError::AlreadyExists(destination.to_path_buf()));
+            }
+            let confirmed = dialoguer::Confirm::new()
+                .with_prompt(format!("overwrite {}?", destination.display()))
+                .default(false)
+                .interact()
+                .map_err(|e| DownloadError::io("Failed to read overwrite confirmation", std::io::Error::other(e)))?;
+            if !confirmed {
+                self.status(format_args!("Skipping {}: not overwriting existing file", destination.display()));
+            }
+            return Ok(confirmed);
+        }
+
+        Err(DownloadError::AlreadyExists(destination.to_path_buf()))
+    }
+
+    fn get_archive_kind(&self, destination: &Path, bytes: &[u8]) -> Option<ArchiveKind> {
+        match self.sniff_mime_type(bytes) {
+            Some("application/zip") => return Some(ArchiveKind::Zip),
+            Some("application/gzip") => return Some(ArchiveKind::TarGz),
+            _ => {}
+        }
+
+        let is_tar = bytes.get(257..262) == Some(b"ustar".as_slice())
+            || destination.extension().is_some_and(|e| e.eq_ignore_ascii_case("tar"));
+        is_tar.then_some(ArchiveKind::Tar)
+    }
+
+    fn extract_file(&self, kind: ArchiveKind, archive: &Path, target: &Path) -> Result<()> {
+        let file = File::open(archive)
+            .map_err(|e| DownloadError::io("Failed to reopen downloaded archive", e))?;
+        self.extract_archive(kind, std::io::BufReader::new(file), target)
+    }
+
+    /// Unpacks an archive into `target`, refusing any entry whose path would land outside it.
+    fn extract_archive<R: std::io::Read + std::io::Seek>(&self, kind: ArchiveKind, reader: R, target: &Path) -> Result<()> {
+        std::fs::create_dir_all(target)
+            .map_err(|e| DownloadError::io("Failed to create extraction directory", e))?;
+
+        match kind {
+            ArchiveKind::Zip => {
+                let mut archive = zip::ZipArchive::new(reader)
+                    .map_err(|e| DownloadError::Archive(format!("Failed to read zip archive: {}", e)))?;
+                for index in 0..archive.len() {
+                    let mut entry = archive
+                        .by_index(index)
+                        .map_err(|e| DownloadError::Archive(format!("Failed to read zip entry: {}", e)))?;
+                    let relative = entry
+                        .enclosed_name()
+                        .ok_or_else(|| {
+                            DownloadError::Archive(format!(
+                                "Refusing to extract '{}': path escapes the target directory",
+                                entry.name()
+                            ))
+                        })?;
+                    let out_path = target.join(relative);
+
+                    if entry.is_dir() {
+                        std::fs::create_dir_all(&out_path)
+                            .map_err(|e| DownloadError::io(format!("Failed to create directory {}", out_path.display()), e))?;
+                        continue;
+                    }
+                    if let Some(parent) = out_path.parent() {
+                        std::fs::create_dir_all(parent)
+                            .map_err(|e| DownloadError::io(format!("Failed to create directory {}", parent.display()), e))?;
+                    }
+                    let mut out = File::create(&out_path)
+                        .map_err(|e| DownloadError::io(format!("Failed to create {}", out_path.display()), e))?;
+                    std::io::copy(&mut entry, &mut out)
+                        .map_err(|e| DownloadError::io(format!("Failed to extract {}", out_path.display()), e))?;
+                }
+            }
+            ArchiveKind::Tar => self.extract_tar(reader, target)?,
+            ArchiveKind::TarGz => self.extract_tar(flate2::read::GzDecoder::new(reader), target)?,
+        }
+
+        Ok(())
+    }
+
+    /// The format of an archive to create for `--archive`, from its extension alone.
+    fn output_archive_kind(&self, archive: &Path) -> Result<ArchiveKind> {
+        let name = archive.file_name().unwrap_or_default().to_string_lossy().to_ascii_lowercase();
+        if name.ends_with(".zip") {
+            Ok(ArchiveKind::Zip)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Ok(ArchiveKind::TarGz)
+        } else if name.ends_with(".tar") {
+            Ok(ArchiveKind::Tar)
+        } else {
+            Err(DownloadError::Archive(format!(
+                "Can't tell which archive format to write for {}: use a .zip, .tar, .tar.gz or .tgz extension",
+                archive.display()
+            )))
+        }
+    }
+
+    /// Writes every file in `source` to a new archive at `archive`, named by filename, in
+    /// filename order. The archive is built under a temporary name and only then moved into
+    /// place. Returns how many files it holds.
+    fn pack_archive(&self, kind: ArchiveKind, source: &Path, archive: &Path) -> Result<usize> {
+        let read_error = |e| DownloadError::io(format!("Failed to read {}", source.display()), e);
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(source).map_err(read_error)? {
+            let path = entry.map_err(read_error)?.path();
+            if path.is_file() {
+                files.push(path);
+            }
+        }
+        files.sort();
+
+        let temp_path = AssetSink::temp_path(archive);
+        let output = File::create(&temp_path)
+            .map_err(|e| DownloadError::io(format!("Failed to create {}", temp_path.display()), e))?;
+        let written = match kind {
+            ArchiveKind::Zip => self.pack_zip(output, &files),
+            ArchiveKind::Tar => self.pack_tar(output, &files).map(drop),
+            ArchiveKind::TarGz => self
+                .pack_tar(flate2::write::GzEncoder::new(output, flate2::Compression::default()), &files)
+                .and_then(|encoder| encoder.finish().map(drop).map_err(|e| DownloadError::io("Failed to finish archive", e))),
+        };
+        let moved = written.and_then(|_| {
+            std::fs::rename(&temp_path, archive)
+                .map_err(|e| DownloadError::io(format!("Failed to move archive to {}", archive.display()), e))
+        });
+        if let Err(e) = moved {
+            let _ = std::fs::remove_file(&temp_path);
+            return Err(e);
+        }
+        Ok(files.len())
+    }
+
+    fn pack_zip(&self, output: File, files: &[PathBuf]) -> Result<()> {
+        let archive_error = |e: zip::result::ZipError| DownloadError::Archive(format!("Failed to write zip archive: {}", e));
+        let mut zip = zip::ZipWriter::new(output);
+        let options = zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        for path in files {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            zip.start_file(name, options).map_err(archive_error)?;
+            let mut file = File::open(path).map_err(|e| DownloadError::io(format!("Failed to read {}", path.display()), e))?;
+            std::io::copy(&mut file, &mut zip).map_err(|e| DownloadError::io(format!("Failed to pack {}", path.display()), e))?;
+        }
+        zip.finish().map_err(archive_error)?;
+        Ok(())
+    }
+
+    fn pack_tar<W: Write>(&self, output: W, files: &[PathBuf]) -> Result<W> {
+        let mut tar = tar::Builder::new(output);
+        for path in files {
+            let name = path.file_name().unwrap_or_default();
+            tar.append_path_with_name(path, name)
+                .map_err(|e| DownloadError::io(format!("Failed to pack {}", path.display()), e))?;
+        }
+        tar.into_inner().map_err(|e| DownloadError::io("Failed to finish archive", e))
+    }
+
+    fn extract_tar(&self, reader: impl std::io::Read, target: &Path) -> Result<()> {
+        let mut archive = tar::Archive::new(reader);
+        let entries = archive
+            .entries()
+            .map_err(|e| DownloadError::Archive(format!("Failed to read tar archive: {}", e)))?;
+        for entry in entries {
+            let mut entry = entry.map_err(|e| DownloadError::Archive(format!("Failed to read tar entry: {}", e)))?;
+            let name = entry.path().map(|p| p.display().to_string()).unwrap_or_default();
+            // unpack_in refuses (returns false) for entries that would escape the target
+            let unpacked = entry
+                .unpack_in(target)
+                .map_err(|e| DownloadError::io(format!("Failed to extract '{}'", name), e))?;
+            if !unpacked {
+                return Err(DownloadError::Archive(format!(
+                    "Refusing to extract '{}': path escapes the target directory",
+                    name
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves a finished temporary file to `wanted`, or to `name (1).ext`, `name (2).ext`, ... if
+    /// that is taken. Claiming the name with a hard link fails if it exists, so concurrent
+    /// downloads can't end up replacing each other.
+    fn move_to_unique_path(&self, temp_path: &Path, wanted: &Path) -> Result<PathBuf> {
+        let stem = wanted.file_stem().unwrap_or_default().to_string_lossy();
+        let extension = wanted.extension().map(|ext| format!(".{}", ext.to_string_lossy())).unwrap_or_default();
+        for n in 0..10_000 {
+            let candidate = match n {
+                0 => wanted.to_path_buf(),
+                n => wanted.with_file_name(format!("{} ({}){}", stem, n, extension)),
+            };
+            match std::fs::hard_link(temp_path, &candidate) {
+                Ok(()) => {
+                    let _ = std::fs::remove_file(temp_path);
+                    return Ok(candidate);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                // No hard links on this filesystem, so fall back to checking first
+                Err(_) if candidate.exists() => continue,
+                Err(_) => {
+                    std::fs::rename(temp_path, &candidate).map_err(|e| DownloadError::io("Failed to move download into place", e))?;
+                    return Ok(candidate);
+                }
+            }
+        }
+        Err(DownloadError::AlreadyExists(wanted.to_path_buf()))
+    }
+
+    /// For `--hash-name`, swaps the asset ID in the filename for a prefix of the content's
+    /// SHA-256, keeping the detected extension.
+    fn hashed_path(&self, destination: &Path, hash: &str) -> PathBuf {
+        let prefix = &hash[..self.config.hash_name_length.min(hash.len())];
+        let filename = match destination.extension() {
+            Some(extension) => format!("{}.{}", prefix, extension.to_string_lossy()),
+            None => prefix.to_string(),
+        };
+        destination.with_file_name(filename)
+    }
+
+    /// Under `--replace-existing-only`, only files that already exist get refreshed.
+    fn skip_missing_for_refresh(&self, asset_id: &str, final_path: &Path) -> bool {
+        if self.config.replace_existing_only && !final_path.exists() {
+            self.status(format_args!("Skipping {}: {} does not exist yet (--replace-existing-only)", asset_id, final_path.display()));
+            return true;
+        }
+        false
+    }
+
+    /// A byte progress bar when the size is known, a spinner with a byte counter otherwise,
+    /// labelled with the start of the asset ID. Hidden under `--quiet` and when stderr isn't a
+    /// terminal.
+    fn progress_bar(&self, asset_id: &str, content_length: Option<u64>) -> ProgressBar {
+        if self.config.quiet || !std::io::stderr().is_terminal() {
+            return ProgressBar::hidden();
+        }
+
+        let bar = match content_length {
+            Some(len) => ProgressBar::new(len).with_style(
+                ProgressStyle::with_template("{prefix} {bar:40} {bytes}/{total_bytes} ({bytes_per_sec}, {eta})")
+                    .unwrap_or_else(|_| ProgressStyle::default_bar()),
+            ),
+            None => ProgressBar::new_spinner().with_style(
+                ProgressStyle::with_template("{prefix} {spinner} {bytes} ({bytes_per_sec})")
+                    .unwrap_or_else(|_| ProgressStyle::default_spinner()),
+            ),
+        };
+        let bar = self.progress.add(bar);
+        bar.set_prefix(asset_id.chars().take(8).collect::<String>());
+        bar
+    }
+
+    fn report_success(&self, destination: &Path) {
+        if self.is_stdout(destination) {
+            self.status(format_args!("Successfully downloaded to stdout"));
+        } else if self.config.replace_existing_only {
+            self.status(format_args!("Refreshed {}", destination.display()));
+        } else {
+            self.status(format_args!("Successfully downloaded to {}", destination.display()));
+        }
+    }
+
+    /// Prints how much was transferred, how long it took and the average rate, unless `--quiet`.
+    fn report_transfer(&self, bytes: u64, elapsed: std::time::Duration) {
+        if self.config.quiet {
+            return;
+        }
+        self.status(format_args!("{}", self.transfer_summary(bytes, elapsed)));
+    }
+
+    fn transfer_summary(&self, bytes: u64, elapsed: std::time::Duration) -> String {
+        let secs = elapsed.as_secs_f64();
+        let rate = if secs > 0.0 { (bytes as f64 / secs) as u64 } else { bytes };
+        format!(
+            "Transferred {} in {:.1}s ({}/s)",
+            indicatif::DecimalBytes(bytes),
+            secs,
+            indicatif::DecimalBytes(rate)
+        )
+    }
+
+    /// Under `--print-sha256`, prints the digest in `sha256sum` format. It goes to stderr so it
+    /// can be read apart from the progress messages, and from the asset itself under stdout mode.
+    fn report_sha256(&self, sha256: &str, destination: &Path) {
+        if self.config.print_sha256 {
+            self.progress.suspend(|| eprintln!("{}  {}", sha256, destination.display()));
+        }
+    }
+
+    /// Under `--json`, prints the record of a finished download as one line on stdout. It has
+    /// the same fields as the metadata sidecar.
+    fn report_json(&self, stats: &DownloadStats) {
+        if !self.config.json {
+            return;
+        }
+        if let Ok(json) = serde_json::to_string(stats) {
+            self.progress.suspend(|| println!("{}", json));
+        }
+    }
+
+    /// Prints a progress or result message. While the asset itself is written to stdout these
+    /// go to stderr instead, so they don't end up mixed into the piped bytes. Under `--json`
+    /// they aren't printed at all.
+    fn status(&self, message: std::fmt::Arguments) {
+        if self.config.json {
+            return;
+        }
+        self.progress.suspend(|| {
+            if self.config.stdout {
+                eprintln!("{}", message);
+            } else {
+                println!("{}", message);
+            }
+        });
+    }
+
+    /// A destination of `-` means stdout.
+    fn is_stdout(&self, destination: &Path) -> bool {
+        destination == Path::new("-")
+    }
+
+    /// Stdout, or a FIFO or character device such as `/dev/stdout` or the `/dev/fd/63` of a
+    /// `>(...)` process substitution. These are written to directly, without a temporary file,
+    /// and are never deleted.
+    fn is_stream(&self, destination: &Path) -> bool {
+        if self.is_stdout(destination) {
+            return true;
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+            std::fs::metadata(destination).is_ok_and(|metadata| {
+                let file_type = metadata.file_type();
+                file_type.is_fifo() || file_type.is_char_device()
+            })
+        }
+        #[cfg(not(unix))]
+        false
+    }
+
+    fn write_to_file(&self, destination: &Path, bytes: &[u8]) -> Result<()> {
+        // Create parent directories if they don't exist and --create-dirs allows it
+        if let Some(parent) = destination.parent().filter(|_| self.config.create_dirs) {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| DownloadError::io("Failed to create parent directories", e))?;
+        }
+
+        // Write to file securely
+        let mut file = File::create(destination)
+            .map_err(|e| DownloadError::io("Failed to create destination file", e))?;
+        
+        file.write_all(bytes)
+            .map_err(|e| DownloadError::io("Failed to write to destination file", e))?;
+        
+        file.sync_all()
+            .map_err(|e| DownloadError::io("Failed to sync file to disk", e))?;
+
+        Ok(())
+    }
+}
+
+fn attempts_label(attempts: u32) -> String {
+    if attempts == 1 {
+        "1 attempt".to_string()
+    } else {
+        format!("{} attempts", attempts)
+    }
+}
+
+/// Paces a transfer for `--limit-rate`: sleeping whenever the bytes so far are ahead of the
+/// allowed rate keeps the average at the cap, while the socket's own buffering smooths the rest.
+struct Throttle {
+    bytes_per_sec: u64,
+    started: std::time::Instant,
+    transferred: u64,
+}
+
+impl Throttle {
+    fn new(bytes_per_sec: u64) -> Self {
+        Self { bytes_per_sec, started: std::time::Instant::now(), transferred: 0 }
+    }
+
+    /// Records a chunk and returns how long to wait before reading the next one.
+    fn pause(&mut self, len: usize) -> Option<std::time::Duration> {
+        self.transferred += len as u64;
+        let due = std::time::Duration::from_secs_f64(self.transferred as f64 / self.bytes_per_sec as f64);
+        due.checked_sub(self.started.elapsed()).filter(|pause| !pause.is_zero())
+    }
+}
+
+const DEFAULT_STALL_TIMEOUT_SECS: u64 = 300;
+const HEAD_TIMEOUT_SECS: u64 = 30;
+const MAX_RETRY_AFTER_SECS: u64 = 300;
+
+/// MIME types and their file extensions (without the dot), the single table used in both
+/// directions. When a MIME type has several extensions, or an extension several MIME types, the
+/// first entry wins, so aliases go after the preferred spelling.
+const MIME_TYPES: &[(&str, &str)] = &[
+    // Images
+    ("image/png", "png"),
+    ("image/jpeg", "jpg"),
+    ("image/jpeg", "jpeg"),
+    ("image/jpg", "jpg"),
+    ("image/gif", "gif"),
+    ("image/webp", "webp"),
+    ("image/avif", "avif"),
+    ("image/heic", "heic"),
+    ("image/heif", "heif"),
+    ("image/bmp", "bmp"),
+    ("image/tiff", "tiff"),
+    ("image/tiff", "tif"),
+    ("image/svg+xml", "svg"),
+    ("image/x-icon", "ico"),
+    ("image/vnd.microsoft.icon", "ico"),
+    // Video
+    ("video/mp4", "mp4"),
+    ("video/webm", "webm"),
+    ("video/mpeg", "mpg"),
+    ("video/mpeg", "mpeg"),
+    ("video/quicktime", "mov"),
+    ("video/x-matroska", "mkv"),
+    ("video/x-msvideo", "avi"),
+    // Audio
+    ("audio/mpeg", "mp3"),
+    ("audio/wav", "wav"),
+    ("audio/x-wav", "wav"),
+    ("audio/ogg", "ogg"),
+    ("audio/flac", "flac"),
+    ("audio/x-flac", "flac"),
+    ("audio/aac", "aac"),
+    ("audio/mp4", "m4a"),
+    // Text
+    ("text/plain", "txt"),
+    ("text/markdown", "md"),
+    ("text/html", "html"),
+    ("text/html", "htm"),
+    ("text/css", "css"),
+    ("text/csv", "csv"),
+    ("text/javascript", "js"),
+    ("application/javascript", "js"),
+    ("application/json", "json"),
+    ("application/xml", "xml"),
+    ("text/xml", "xml"),
+    // Documents
+    ("application/pdf", "pdf"),
+    ("application/msword", "doc"),
+    ("application/vnd.openxmlformats-officedocument.wordprocessingml.document", "docx"),
+    ("application/vnd.ms-excel", "xls"),
+    ("application/vnd.openxmlformats-officedocument.spreadsheetml.sheet", "xlsx"),
+    ("application/vnd.ms-powerpoint", "ppt"),
+    ("application/vnd.openxmlformats-officedocument.presentationml.presentation", "pptx"),
+    // Archives
+    ("application/zip", "zip"),
+    ("application/x-zip-compressed", "zip"),
+    ("application/gzip", "gz"),
+    ("application/gzip", "tgz"),
+    ("application/x-gzip", "gz"),
+    ("application/x-tar", "tar"),
+    ("application/x-7z-compressed", "7z"),
+    ("application/x-bzip2", "bz2"),
+    ("application/x-xz", "xz"),
+];
+
+/// Extensions of more than one part. Only the last part would be found in [`MIME_TYPES`], but
+/// `.gz` alone would lose that the file is a tarball.
+const COMPOUND_EXTENSIONS: &[&str] = &["tar.gz", "tar.bz2", "tar.xz"];
+
+/// The hosts a GET went through, each with the status it answered, such as
+/// `github.com 302 -> github-production-user-asset-6210df.s3.amazonaws.com 403`.
+#[derive(Debug, Default)]
+struct RedirectChain(Vec<(String, u16)>);
+
+impl RedirectChain {
+    fn record(&mut self, url: &reqwest::Url, status: reqwest::StatusCode) {
+        let host = &url[url::Position::BeforeHost..url::Position::AfterPort];
+        self.0.push((host.to_string(), status.as_u16()));
+    }
+
+    fn redirected(&self) -> bool {
+        self.0.len() > 1
+    }
+}
+
+impl std::fmt::Display for RedirectChain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let hops: Vec<String> = self.0.iter().map(|(host, status)| format!("{} {}", host, status)).collect();
+        f.write_str(&hops.join(" -> "))
+    }
+}
+
+/// Matches reqwest's default redirect limit.
+const MAX_REDIRECTS: usize = 10;
+
+/// How much of an error response's body goes into the error message, in characters, and how
+/// many bytes are read to get it.
+const ERROR_SNIPPET_LEN: usize = 300;
+const ERROR_BODY_READ_LIMIT: usize = 8 * 1024;
+
+/// How many leading bytes are held back for the type and HTML checks before writing starts.
+const SNIFF_LEN: usize = 512;
+
+/// Receives an asset body chunk by chunk and writes it to disk. The first bytes are held back
+/// until the `--verify-type` and `--on-html` checks have run, so a rejected body never touches
+/// the destination. The body is written to a temporary file that is only renamed over the
+/// destination once complete. The SHA-256 is computed along the way.
+struct AssetSink<'a> {
+    downloader: &'a AssetDownloader,
+    destination: PathBuf,
+    /// Where the body is written until it is complete. Named after the destination as first
+    /// given, so a later `--resume` finds it even if the extension is corrected from the body
+    temp_path: PathBuf,
+    /// The filename was generated in directory mode rather than given by the user
+    auto_named: bool,
+    source: AssetSource,
+    state: SinkState,
+    /// Declared after `state`, so the file is closed before it is deleted
+    cleanup: TempFileGuard,
+    prefix: Vec<u8>,
+    /// Bytes already in the temporary file from an earlier attempt, under `--resume`
+    resumed: u64,
+    hasher: Sha256,
+    size: u64,
+    /// Bytes of the asset seen so far, including any resumed ones, for `--max-size`
+    received: u64,
+    /// When the body started arriving, for the transfer summary
+    started: std::time::Instant,
+    /// The response's ETag and Last-Modified, recorded in the metadata sidecar
+    validators: Validators,
+    progress: ProgressBar,
+}
+
+enum SinkState {
+    Sniffing,
+    Writing {
+        output: SinkOutput,
+        path: PathBuf,
+        /// The archive format when the body is to be unpacked under `--extract`
+        extract: Option<ArchiveKind>,
+    },
+    Skipped,
+}
+
+enum SinkOutput {
+    File(File),
+    Stdout(std::io::Stdout),
+    /// A FIFO or character device, see [`AssetDownloader::is_stream`]
+    Special(File),
+}
+
+/// Deletes the temporary file it holds when dropped.
+struct TempFileGuard(Option<PathBuf>);
+
+impl Drop for TempFileGuard {
+    fn drop(&mut self) {
+        if let Some(path) = self.0.take() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+impl Write for SinkOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            SinkOutput::File(file) | SinkOutput::Special(file) => file.write(buf),
+            SinkOutput::Stdout(stdout) => stdout.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            SinkOutput::File(file) | SinkOutput::Special(file) => file.flush(),
+            SinkOutput::Stdout(stdout) => stdout.flush(),
+        }
+    }
+}
+
+impl<'a> AssetSink<'a> {
+    fn new(
+        downloader: &'a AssetDownloader,
+        destination: &Path,
+        auto_named: bool,
+        source: AssetSource,
+        content_length: Option<u64>,
+    ) -> Self {
+        let progress = downloader.progress_bar(&source.asset_id, content_length);
+        AssetSink {
+            downloader,
+            destination: destination.to_path_buf(),
+            temp_path: Self::temp_path(destination),
+            auto_named,
+            source,
+            state: SinkState::Sniffing,
+            cleanup: TempFileGuard(None),
+            prefix: Vec::new(),
+            resumed: 0,
+            hasher: Sha256::new(),
+            size: 0,
+            received: 0,
+            started: std::time::Instant::now(),
+            validators: Validators::default(),
+            progress,
+        }
+    }
+
+    /// Continues from the partial download in the temporary file: its bytes are hashed, and its
+    /// start is run through the same checks as a fresh body, before new chunks are appended.
+    fn resume(&mut self, offset: u64) -> Result<()> {
+        let mut file = File::open(&self.temp_path)
+            .map_err(|e| DownloadError::io("Failed to open partial download", e))?;
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let read = std::io::Read::read(&mut file, &mut buffer)
+                .map_err(|e| DownloadError::io("Failed to read partial download", e))?;
+            if read == 0 {
+                break;
+            }
+            if self.prefix.len() < SNIFF_LEN {
+                let needed = (SNIFF_LEN - self.prefix.len()).min(read);
+                self.prefix.extend_from_slice(&buffer[..needed]);
+            }
+            self.hasher.update(&buffer[..read]);
+        }
+        self.resumed = offset;
+        self.size = offset;
+        self.received = offset;
+        self.progress.set_position(offset);
+        Ok(())
+    }
+
+    /// Feeds the next chunk. Returns false once the body has been skipped and further chunks
+    /// are not needed.
+    fn push(&mut self, chunk: &[u8]) -> Result<bool> {
+        self.received += chunk.len() as u64;
+        self.downloader.check_size(self.received)?;
+        self.progress.inc(chunk.len() as u64);
+        match &mut self.state {
+            SinkState::Skipped => return Ok(false),
+            SinkState::Sniffing => {
+                self.prefix.extend_from_slice(chunk);
+                if self.prefix.len() >= SNIFF_LEN {
+                    self.start()?;
+                }
+            }
+            SinkState::Writing { .. } => self.write(chunk)?,
+        }
+        Ok(!matches!(self.state, SinkState::Skipped))
+    }
+
+    /// Runs the pre-write checks on the held-back prefix and opens the output file.
+    fn start(&mut self) -> Result<()> {
+        let downloader = self.downloader;
+        let prefix = std::mem::take(&mut self.prefix);
+
+        if let Err(reason) = downloader.verify_content_type(&prefix) {
+            downloader.status(format_args!("Skipping {}: {}", self.source.asset_id, reason));
+            self.state = SinkState::Skipped;
+            return Ok(());
+        }
+        if !downloader.check_html_response(self.source.content_type.as_deref(), &prefix, &self.destination)? {
+            self.state = SinkState::Skipped;
+            return Ok(());
+        }
+        if downloader.config.convert.is_some() {
+            downloader.check_convertible(&self.source, &prefix, &self.destination)?;
+        }
+
+        if self.auto_named {
+            self.apply_sniffed_extension(&prefix);
+        }
+
+        if downloader.is_stream(&self.destination) {
+            let output = match downloader.is_stdout(&self.destination) {
+                true => SinkOutput::Stdout(std::io::stdout()),
+                false => SinkOutput::Special(
+                    std::fs::OpenOptions::new()
+                        .write(true)
+                        .open(&self.destination)
+                        .map_err(|e| DownloadError::io(format!("Failed to open {}", self.destination.display()), e))?,
+                ),
+            };
+            self.state = SinkState::Writing { output, path: self.destination.clone(), extract: None };
+            return self.write(&prefix);
+        }
+
+        let extract = match &downloader.config.extract_dir {
+            Some(_) => {
+                let kind = downloader.get_archive_kind(&self.destination, &prefix);
+                if kind.is_none() {
+                    eprintln!(
+                        "Warning: {} is not a supported archive (zip, tar, tar.gz); saving it without extracting",
+                        self.destination.display()
+                    );
+                }
+                kind
+            }
+            None => None,
+        };
+
+        // The body goes to a temporary file next to the destination, which is only renamed into
+        // place once complete. The final name isn't known yet under --hash-name, and an
+        // extracted archive isn't kept, so those are checked for conflicts later
+        let extract_only = extract.is_some() && !downloader.config.keep_archive;
+        let unique = self.unique_names();
+        if !downloader.config.hash_name && !extract_only && !unique && !downloader.check_existing(&self.destination)? {
+            self.state = SinkState::Skipped;
+            return Ok(());
+        }
+        let path = self.temp_path.clone();
+
+        // Create parent directories if they don't exist and --create-dirs allows it
+        if let Some(parent) = path.parent().filter(|_| downloader.config.create_dirs) {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| DownloadError::io("Failed to create parent directories", e))?;
+        }
+        let file = if self.resumed > 0 {
+            std::fs::OpenOptions::new().append(true).open(&path)
+        } else {
+            File::create(&path)
+        }
+        .map_err(|e| DownloadError::io("Failed to create destination file", e))?;
+
+        // A download cancelled midway, e.g. by Ctrl-C, is dropped without finishing; the guard
+        // then deletes what was written so far. Under --resume it is kept to continue from.
+        if !downloader.config.resume {
+            self.cleanup = TempFileGuard(Some(path.clone()));
+        }
+
+        // When resuming, the start of the prefix came from the file itself: as much of it as
+        // `resume` held back for sniffing, at most SNIFF_LEN bytes
+        let on_disk = self.resumed.min(SNIFF_LEN as u64) as usize;
+        self.state = SinkState::Writing { output: SinkOutput::File(file), path, extract };
+        self.write(&prefix[on_disk..])
+    }
+
+    fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        if let SinkState::Writing { output, .. } = &mut self.state {
+            output.write_all(chunk)
+                .map_err(|e| DownloadError::io("Failed to write to destination file", e))?;
+            self.hasher.update(chunk);
+            self.size += chunk.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// Completes the download once the body has been fully received.
+    fn finish(mut self) -> Result<Option<DownloadOutcome>> {
+        if matches!(self.state, SinkState::Sniffing) {
+            self.start()?;
+        }
+        self.progress.finish_and_clear();
+
+        let SinkState::Writing { output, path, extract } = std::mem::replace(&mut self.state, SinkState::Skipped) else {
+            return Ok(None);
+        };
+
+        // On success the temporary file has been moved into place; on failure it is removed so no
+        // partial download is left behind. Bytes already written to stdout or a pipe can't be taken back.
+        let temp_path = path.clone();
+        let to_stdout = self.downloader.is_stream(&temp_path);
+        let result = self.complete(output, path, extract);
+        if result.is_err() && !to_stdout {
+            let _ = std::fs::remove_file(&temp_path);
+        }
+        result
+    }
+
+    /// Flushes the output and verifies the checksum, then moves the temporary file into place,
+    /// unpacking it instead under `--extract`.
+    fn complete(mut self, output: SinkOutput, path: PathBuf, extract: Option<ArchiveKind>) -> Result<Option<DownloadOutcome>> {
+        let downloader = self.downloader;
+
+        let to_stdout = matches!(output, SinkOutput::Stdout(_) | SinkOutput::Special(_));
+        match output {
+            SinkOutput::File(file) => file
+                .sync_all()
+                .map_err(|e| DownloadError::io("Failed to sync file to disk", e))?,
+            SinkOutput::Stdout(mut stdout) => {
+                stdout.flush().map_err(|e| DownloadError::io("Failed to write to stdout", e))?;
+            }
+            SinkOutput::Special(mut file) => {
+                file.flush().map_err(|e| DownloadError::io(format!("Failed to write to {}", path.display()), e))?;
+            }
+        }
+
+        let sha256 = format!("{:x}", self.hasher.clone().finalize());
+        if let Some(expected) = downloader.config.sha256.as_ref().filter(|expected| **expected != sha256) {
+            return Err(DownloadError::ChecksumMismatch { expected: expected.clone(), actual: sha256 });
+        }
+
+        if to_stdout {
+            downloader.report_sha256(&sha256, &path);
+            downloader.report_success(&path);
+            downloader.report_transfer(self.size - self.resumed, self.started.elapsed());
+            return Ok(Some(DownloadOutcome::new(path, self.size, self.source.content_type)));
+        }
+
+        let extract_dir = downloader.config.extract_dir.as_deref().unwrap_or(Path::new("."));
+        if let Some(kind) = extract.filter(|_| !downloader.config.keep_archive) {
+            let result = downloader.extract_file(kind, &path, extract_dir);
+            let _ = std::fs::remove_file(&path);
+            result?;
+            downloader.report_sha256(&sha256, &self.destination);
+            downloader.status(format_args!("Extracted {} to {}", self.destination.display(), extract_dir.display()));
+            downloader.report_transfer(self.size - self.resumed, self.started.elapsed());
+            let outcome = DownloadOutcome::new(extract_dir.to_path_buf(), self.size, self.source.content_type.clone());
+            downloader.report_json(&DownloadStats::new(self.source, downloader.config.repo.as_ref(), extract_dir, self.size, sha256));
+            return Ok(Some(outcome));
+        }
+
+        let wanted_path = if downloader.config.hash_name {
+            let hashed = downloader.hashed_path(&self.destination, &sha256);
+            let write = if self.unique_names() { Ok(true) } else { downloader.check_existing(&hashed) };
+            if !matches!(write, Ok(true)) {
+                let _ = std::fs::remove_file(&path);
+                return write.map(|_| None);
+            }
+            hashed
+        } else {
+            self.destination.clone()
+        };
+        let final_path = if self.unique_names() {
+            downloader.move_to_unique_path(&path, &wanted_path)?
+        } else {
+            // Same directory, so the rename is atomic
+            std::fs::rename(&path, &wanted_path)
+                .map_err(|e| DownloadError::io("Failed to move download into place", e))?;
+            wanted_path
+        };
+        self.cleanup.0 = None;
+
+        if let Some(kind) = extract {
+            downloader.extract_file(kind, &final_path, extract_dir)?;
+            downloader.status(format_args!("Extracted {} to {}", final_path.display(), extract_dir.display()));
+        }
+        // Size and checksum keep describing the downloaded bytes
+        let (final_path, content_type) = match downloader.config.convert {
+            Some(format) => {
+                let converted = downloader.convert_image(&final_path, format)?;
+                let content_type = downloader.get_mime_type_from_extension(format.extension()).map(str::to_string);
+                (converted, content_type.or(self.source.content_type.clone()))
+            }
+            None => (final_path, self.source.content_type.clone()),
+        };
+
+        if downloader.config.dedupe {
+            downloader.link_duplicate(&final_path, &sha256)?;
+        }
+
+        let outcome = DownloadOutcome::new(final_path.clone(), self.size, content_type);
+        let mut stats = DownloadStats::new(self.source, downloader.config.repo.as_ref(), &final_path, self.size, sha256.clone());
+        stats.validators = self.validators;
+        if downloader.config.write_metadata_sidecar {
+            downloader.write_metadata_sidecar(&final_path, &stats)?;
+        }
+        downloader.report_sha256(&sha256, &final_path);
+        downloader.report_success(&final_path);
+        downloader.report_transfer(self.size - self.resumed, self.started.elapsed());
+        downloader.report_json(&stats);
+        Ok(Some(outcome))
+    }
+
+    /// A generated `.bin` name means the headers gave nothing better, which is common behind
+    /// S3 redirects serving `application/octet-stream`. Use the body's magic bytes instead.
+    fn apply_sniffed_extension(&mut self, prefix: &[u8]) {
+        if self.destination.extension().is_none_or(|ext| ext != "bin") {
+            return;
+        }
+        let Some(mime_type) = self.downloader.sniff_mime_type(prefix) else {
+            return;
+        };
+
+        let extension = self.downloader.get_extension_from_mime_type(mime_type);
+        let renamed = self.destination.with_extension(extension.trim_start_matches('.'));
+        if self.downloader.config.verbose {
+            eprintln!("Sniffed {} from the content, saving as {}", mime_type, renamed.display());
+        }
+        self.destination = renamed;
+    }
+
+    /// Replaces the `.bin` fallback with the extension from the GET response's headers, for
+    /// when the HEAD probe failed. The content is sniffed afterwards if they don't tell either.
+    fn apply_header_extension(&mut self, detected: &DetectedType) {
+        if detected.source == TypeSource::Fallback || self.destination.extension().is_none_or(|ext| ext != "bin") {
+            return;
+        }
+
+        let renamed = self.destination.with_extension(detected.extension.trim_start_matches('.'));
+        if self.downloader.config.verbose {
+            eprintln!("Detected {} from the download's {}, saving as {}", detected.extension, detected.source, renamed.display());
+        }
+        self.destination = renamed;
+    }
+
+    fn unique_names(&self) -> bool {
+        self.downloader.config.unique_names && self.auto_named
+    }
+
+    /// Whether the download can start over. Bytes already written to stdout or a pipe can't be
+    /// taken back.
+    fn can_retry(&self) -> bool {
+        !matches!(self.state, SinkState::Writing { output: SinkOutput::Stdout(_) | SinkOutput::Special(_), .. })
+    }
+
+    /// Drops a partially written body, e.g. before retrying. Under `--resume` it is kept for the
+    /// next attempt to continue from.
+    fn abandon(self) {
+        self.progress.finish_and_clear();
+        if let SinkState::Writing { path, .. } = self.state {
+            if !self.downloader.is_stream(&path) && !self.downloader.config.resume {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+
+    /// Like [`AssetSink::abandon`], but deletes the temporary file even under `--resume`.
+    fn discard(self) {
+        let temp_path = self.temp_path.clone();
+        let stdout = self.downloader.is_stream(&self.destination);
+        self.abandon();
+        if !stdout {
+            let _ = std::fs::remove_file(temp_path);
+        }
+    }
+
+    fn temp_path(destination: &Path) -> PathBuf {
+        let filename = destination.file_name().unwrap_or_default().to_string_lossy();
+        destination.with_file_name(format!(".{}.part", filename))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_valid_asset_id_valid() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+        
+        // Valid UUID format
+        assert!(downloader.is_valid_asset_id("1234abcd-1234-1234-1234-1234abcd1234"));
+        assert!(downloader.is_valid_asset_id("ABCD1234-5678-9012-3456-789012345678"));
+
+        // Numeric release asset IDs only with a release repository
+        assert!(!downloader.is_valid_asset_id("123456789"));
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let config = Config { release_repo: Some("YuitoSato/gh-asset".parse().unwrap()), ..Config::default() };
+        let downloader = AssetDownloader::with_auth(auth, config);
+        assert!(downloader.is_valid_asset_id("123456789"));
+        assert!(downloader.is_valid_asset_id("1234abcd-1234-1234-1234-1234abcd1234"));
+        assert!(!downloader.is_valid_asset_id("0"));
+        assert!(!downloader.is_valid_asset_id("-123"));
+        assert!(!downloader.is_valid_asset_id("+123"));
+        assert!(!downloader.is_valid_asset_id("99999999999999999999999"));
+    }
+
+    #[test]
+    fn test_is_valid_asset_id_invalid() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+        
+        assert!(!downloader.is_valid_asset_id(""));
+        assert!(!downloader.is_valid_asset_id("abc"));
+        assert!(!downloader.is_valid_asset_id("invalid@id"));
+        assert!(!downloader.is_valid_asset_id("id with spaces"));
+        assert!(!downloader.is_valid_asset_id("a1b2c3d4e5")); // No hyphen
+        assert!(!downloader.is_valid_asset_id("../../../etc/passwd"));
+        assert!(!downloader.is_valid_asset_id("'; rm -rf /; '"));
+
+        // Hyphenated strings that merely look ID-like
+        assert!(!downloader.is_valid_asset_id("a1234567890123456789b-c-d"));
+        assert!(!downloader.is_valid_asset_id("1234567890123456789x-1234567x-1234567x"));
+        assert!(!downloader.is_valid_asset_id("release-v1.2.3-linux-amd64"));
+        assert!(!downloader.is_valid_asset_id("1234abcd-1234-1234-1234-1234abcd12345"));
+        assert!(!downloader.is_valid_asset_id("1234abcd-1234-1234-12341-234abcd1234"));
+        assert!(!downloader.is_valid_asset_id("1234abcg-1234-1234-1234-1234abcd1234"));
+        assert!(!downloader.is_valid_asset_id("123456789")); // Numeric without --release-asset
+    }
+
+    #[test]
+    fn test_extract_asset_ids() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+
+        let markdown = "![screenshot](https://github.com/user-attachments/assets/1234abcd-1234-1234-1234-1234abcd1234)";
+        assert_eq!(downloader.extract_asset_ids(markdown), vec!["1234abcd-1234-1234-1234-1234abcd1234"]);
+
+        let text = "see abcd1234-5678-9012-3456-789012345678, and again abcd1234-5678-9012-3456-789012345678.";
+        assert_eq!(downloader.extract_asset_ids(text), vec!["abcd1234-5678-9012-3456-789012345678"]);
+
+        assert!(downloader.extract_asset_ids("nothing here").is_empty());
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let http = |status| DownloadError::HttpStatus { status, message: String::new() };
+        assert_eq!(DownloadError::InvalidAssetId(String::new()).exit_code(), 2);
+        assert_eq!(DownloadError::AuthFailed(String::new()).exit_code(), 3);
+        assert_eq!(http(403).exit_code(), 3);
+        assert_eq!(http(404).exit_code(), 4);
+        assert_eq!(http(500).exit_code(), 1);
+        assert_eq!(DownloadError::BatchFailed { failed: 1, total: 2 }.exit_code(), 1);
+    }
+
+    #[test]
+    fn test_parse_asset_id_list() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+        let text = "# screenshots from the bug report\n\
+            1234abcd-1234-1234-1234-1234abcd1234\n\
+            \n\
+            https://github.com/user-attachments/assets/abcd1234-5678-9012-3456-789012345678\n\
+            not-an-id\n";
+
+        let (asset_ids, malformed) = downloader.parse_asset_id_list(text);
+        assert_eq!(asset_ids, vec!["1234abcd-1234-1234-1234-1234abcd1234", "abcd1234-5678-9012-3456-789012345678"]);
+        assert_eq!(malformed.len(), 1);
+        assert_eq!(malformed[0].0, 5);
+    }
+
+    #[test]
+    fn test_find_asset_id() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+        let id = "1234abcd-1234-1234-1234-1234abcd1234";
+
+        assert_eq!(downloader.find_asset_id(&format!(" {}\n", id)).unwrap(), id);
+        let copied = format!("See https://github.com/user-attachments/assets/{} for the crash", id);
+        assert_eq!(downloader.find_asset_id(&copied).unwrap(), id);
+        assert!(downloader.find_asset_id("meeting notes").is_err());
+        let two = format!("{} and abcd1234-5678-9012-3456-789012345678", id);
+        assert!(downloader.find_asset_id(&two).unwrap_err().to_string().contains("several"));
+    }
+
+    #[test]
+    fn test_clean_asset_id() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let config = Config { lenient_id: true, ..Config::default() };
+        let downloader = AssetDownloader::with_auth(auth, config);
+
+        let id = "1234abcd-1234-1234-1234-1234abcd1234";
+        assert_eq!(downloader.clean_asset_id(id).unwrap(), id);
+        assert_eq!(downloader.clean_asset_id(&format!("!({})", id)).unwrap(), id);
+        assert_eq!(
+            downloader.clean_asset_id(&format!("![](https://github.com/user-attachments/assets/{})", id)).unwrap(),
+            id
+        );
+        assert!(downloader.clean_asset_id("no id at all").is_err());
+        assert!(downloader
+            .clean_asset_id("1234abcd-1234-1234-1234-1234abcd1234 abcd1234-5678-9012-3456-789012345678")
+            .is_err());
+
+        // Without --lenient-id only attachment URLs are unwrapped; anything else is passed
+        // through for normal validation
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+        assert_eq!(downloader.clean_asset_id(&format!("!({})", id)).unwrap(), format!("!({})", id));
+        assert_eq!(
+            downloader.clean_asset_id(&format!("https://github.com/user-attachments/assets/{}", id)).unwrap(),
+            id
+        );
+    }
+
+    #[test]
+    fn test_parse_issue_ref() {
+        let issue: IssueRef = "YuitoSato/gh-asset#42".parse().unwrap();
+        assert_eq!(issue, IssueRef { owner: "YuitoSato".to_string(), repo: "gh-asset".to_string(), number: 42 });
+        assert_eq!(issue.to_string(), "YuitoSato/gh-asset#42");
+        assert_eq!(issue.api_url("github.com"), "https://api.github.com/repos/YuitoSato/gh-asset/issues/42");
+        assert_eq!(
+            issue.api_url("github.mycorp.com"),
+            "https://github.mycorp.com/api/v3/repos/YuitoSato/gh-asset/issues/42"
+        );
+
+        for invalid in ["gh-asset#42", "YuitoSato/gh-asset", "YuitoSato/gh-asset#abc", "/gh-asset#1", "a/b/c#1"] {
+            assert!(invalid.parse::<IssueRef>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_extract_attachment_ids() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+
+        let body = "Repro:\n![one](https://github.com/user-attachments/assets/1234abcd-1234-1234-1234-1234abcd1234)\n\
+            request id abcd1234-5678-9012-3456-789012345678\n\
+            <img src=\"https://github.com/user-attachments/assets/1234abcd-1234-1234-1234-1234abcd1234\">";
+        assert_eq!(downloader.extract_attachment_ids(body), vec!["1234abcd-1234-1234-1234-1234abcd1234"]);
+    }
+
+    #[test]
+    fn test_asset_id_from_url() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+        let id = "1234abcd-1234-1234-1234-1234abcd1234";
+        let some = Some(id.to_string());
+
+        assert_eq!(downloader.asset_id_from_url(&format!("https://github.com/user-attachments/assets/{}", id)), some);
+        assert_eq!(downloader.asset_id_from_url(&format!("github.com/user-attachments/assets/{}/", id)), some);
+        assert_eq!(downloader.asset_id_from_url(&format!("https://github.com/user-attachments/assets/{}?raw=1", id)), some);
+        assert_eq!(downloader.asset_id_from_url(&format!("![Screenshot 2024](https://github.com/user-attachments/assets/{})", id)), some);
+
+        assert_eq!(downloader.asset_id_from_url(id), None);
+        assert_eq!(downloader.asset_id_from_url(&format!("https://github.com/user-attachments/assets/{})", id)), None);
+        assert_eq!(downloader.asset_id_from_url(&format!("see https://github.com/user-attachments/assets/{}", id)), None);
+        assert_eq!(downloader.asset_id_from_url("https://github.com/user-attachments/assets/short"), None);
+
+        // Under --host, URLs on that host are recognized instead
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let config = Config { host: Some("github.mycorp.com".to_string()), ..Config::default() };
+        let downloader = AssetDownloader::with_auth(auth, config);
+        assert_eq!(downloader.asset_id_from_url(&format!("https://github.mycorp.com/user-attachments/assets/{}", id)), some);
+    }
+
+    #[test]
+    fn test_validate_destination_path_safe() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+        
+        // Safe relative paths
+        assert!(downloader.validate_destination_path("test.png").is_ok());
+        assert!(downloader.validate_destination_path("./test.png").is_ok());
+        assert!(downloader.validate_destination_path("subdir/test.png").is_ok());
+
+        // `-` is stdout rather than a path
+        assert_eq!(downloader.validate_destination_path("-").unwrap(), PathBuf::from("-"));
+    }
+
+    #[test]
+    fn test_validate_destination_path_unsafe() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+        
+        // Path traversal attempts
+        assert!(downloader.validate_destination_path("../test.png").is_err());
+        assert!(downloader.validate_destination_path("../../etc/passwd").is_err());
+        assert!(downloader.validate_destination_path("/etc/passwd").is_err());
+        assert!(downloader.validate_destination_path("/usr/bin/evil").is_err());
+        assert!(downloader.validate_destination_path("src/../../test.png").is_err());
+        assert!(matches!(
+            downloader.validate_destination_path("../test.png"),
+            Err(DownloadError::PathRejected(_))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_download_to_fifo() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config { quiet: true, ..Config::default() });
+        let dir = scratch_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let fifo = dir.join("pipe");
+        assert!(Command::new("mkfifo").arg(&fifo).status().unwrap().success());
+
+        let validated = downloader.validate_destination_path(&fifo.to_string_lossy()).unwrap();
+        let reader = {
+            let fifo = fifo.clone();
+            std::thread::spawn(move || std::fs::read(fifo).unwrap())
+        };
+        let source = AssetSource {
+            asset_id: "1234abcd-1234-1234-1234-1234abcd1234".to_string(),
+            source_url: "https://github.com/user-attachments/assets/1234abcd-1234-1234-1234-1234abcd1234".to_string(),
+            final_url: None,
+            content_type: Some("text/plain".to_string()),
+        };
+        let mut sink = AssetSink::new(&downloader, &validated, false, source, Some(5));
+        sink.push(b"hello").unwrap();
+        let outcome = sink.finish().unwrap().unwrap();
+        let received = reader.join().unwrap();
+        let still_there = fifo.exists();
+        let leftovers = std::fs::read_dir(&dir).unwrap().count();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(received, b"hello");
+        assert_eq!(outcome.path, fifo);
+        assert!(still_there);
+        assert_eq!(leftovers, 1);
+    }
+
+    #[test]
+    fn test_is_system_dir() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+
+        // `..` only counts as a whole component
+        assert!(downloader.validate_destination_path("my..backup.png").is_ok());
+
+        #[cfg(not(windows))]
+        {
+            assert!(downloader.is_system_dir(Path::new("/usr/bin/evil")));
+            assert!(downloader.is_system_dir(Path::new("/etc")));
+            assert!(!downloader.is_system_dir(Path::new("/usrdata/image.png")));
+            assert!(!downloader.is_system_dir(Path::new("/home/me/Downloads")));
+            assert!(downloader.is_system_dir(Path::new("/usr/../etc")));
+            assert!(downloader.is_system_dir(Path::new("/home/./../etc/passwd")));
+            assert!(!downloader.is_system_dir(Path::new("/etc/../home/me")));
+        }
+        #[cfg(windows)]
+        {
+            assert!(downloader.is_system_dir(Path::new(r"C:\Windows\System32\evil.dll")));
+            assert!(downloader.is_system_dir(Path::new(r"\\?\c:\program files\app")));
+            assert!(!downloader.is_system_dir(Path::new(r"C:\Users\me\Downloads")));
+            assert!(downloader.is_system_dir(Path::new(r"C:\Windows\..\Windows\System32")));
+            assert!(downloader.is_system_dir(Path::new(r"C:\Users\..\Windows\System32")));
+            assert!(downloader.validate_destination_path(r"src\..\..\test.png").is_err());
+        }
+    }
+
+    #[test]
+    fn test_validate_destination_path_allow_outside_cwd() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let config = Config { allow_outside_cwd: true, ..Config::default() };
+        let downloader = AssetDownloader::with_auth(auth, config);
+
+        // Climb to the filesystem root and back down into the temp directory
+        let depth = std::env::current_dir().unwrap().components().count();
+        let up = "../".repeat(depth);
+        let temp = std::env::temp_dir().canonicalize().unwrap();
+        let outside = format!("{}{}/assets/", up, temp.strip_prefix("/").unwrap().display());
+        assert!(downloader.validate_destination_path(&outside).is_ok());
+        assert!(downloader.validate_destination_path("my..backup.png").is_ok());
+
+        // System directories stay off-limits, however they are reached
+        let to_etc = format!("{}etc/passwd", up);
+        assert!(downloader.validate_destination_path(&to_etc).is_err());
+        assert!(downloader.validate_destination_path("/etc/passwd").is_err());
+        let absolute_to_etc = format!("{}/{}etc/passwd", temp.display(), "../".repeat(temp.components().count() - 1));
+        assert!(matches!(downloader.validate_destination_path(&absolute_to_etc), Err(DownloadError::PathRejected(_))));
+        assert!(downloader.validate_destination_path("/tmp/../etc/passwd").is_err());
+        assert!(downloader.validate_destination_path(&format!("{}/../{}/assets/", temp.display(), temp.file_name().unwrap().to_string_lossy())).is_ok());
+    }
+
+    #[test]
+    fn test_validate_destination_path_custom_root() {
+        let current_dir = std::env::current_dir().unwrap();
+        let sibling = format!("../{}-sibling/assets/image.png", current_dir.file_name().unwrap().to_string_lossy());
+
+        // Rejected under the default cwd root
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+        assert!(downloader.validate_destination_path(&sibling).is_err());
+
+        // Allowed when the parent directory is the root
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let config = Config { root: Some(current_dir.parent().unwrap().to_path_buf()), ..Config::default() };
+        let downloader = AssetDownloader::with_auth(auth, config);
+        assert!(downloader.validate_destination_path(&sibling).is_ok());
+        assert!(downloader.validate_destination_path("subdir/test.png").is_ok());
+
+        // Still no escaping the chosen root
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let config = Config { root: Some(current_dir.join("src")), ..Config::default() };
+        let downloader = AssetDownloader::with_auth(auth, config);
+        assert!(downloader.validate_destination_path("src/../../outside/image.png").is_err());
+        assert!(downloader.validate_destination_path("Cargo.toml").is_err());
+        assert!(downloader.validate_destination_path("src/new/image.png").is_ok());
+
+        // A root spanning system directories still keeps relative paths out of them
+        #[cfg(unix)]
+        {
+            let auth = GitHubAuth { token: "fake_token".to_string() };
+            let config = Config { root: Some(PathBuf::from("/")), ..Config::default() };
+            let filesystem_root = AssetDownloader::with_auth(auth, config);
+            let up = "../".repeat(current_dir.canonicalize().unwrap().components().count() - 1);
+            let temp = std::env::temp_dir().canonicalize().unwrap();
+            let into_temp = format!("{}{}/assets/", up, temp.strip_prefix("/").unwrap().display());
+            assert!(filesystem_root.validate_destination_path(&into_temp).is_ok());
+            let into_etc = format!("{}etc/cron.d/x", up);
+            assert!(matches!(filesystem_root.validate_destination_path(&into_etc), Err(DownloadError::PathRejected(_))));
+        }
+
+        // `..` in an absolute path can't slip into a system directory either
+        #[cfg(unix)]
+        {
+            let temp = std::env::temp_dir().canonicalize().unwrap();
+            let depth = temp.components().count() - 1;
+            let via_temp = format!("{}/{}etc/cron.d/x", temp.display(), "../".repeat(depth));
+            assert!(matches!(downloader.validate_destination_path(&via_temp), Err(DownloadError::PathRejected(_))));
+            assert!(downloader.validate_destination_path("/tmp/../etc/cron.d/x").is_err());
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_validate_destination_path_follows_symlinks_into_system_dirs() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+        let dir = scratch_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        std::os::unix::fs::symlink("/etc", dir.join("link")).unwrap();
+
+        let through_link = downloader.validate_destination_path(&dir.join("link/cron.d/x").to_string_lossy());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(matches!(through_link, Err(DownloadError::PathRejected(_))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_canonicalize_nearest_resolves_symlinks_before_parent_dir() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let dir = scratch_dir();
+        std::fs::create_dir_all(dir.join("root")).unwrap();
+        std::fs::create_dir_all(dir.join("outside/inner")).unwrap();
+        std::os::unix::fs::symlink(dir.join("outside/inner"), dir.join("root/link")).unwrap();
+        let dir = dir.canonicalize().unwrap();
+
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+        let resolved = downloader.canonicalize_nearest(&dir.join("root/link/../x.png")).unwrap();
+
+        // The same path relative to the current directory, checked against a root that the link
+        // points out of
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let config = Config { root: Some(dir.join("root")), ..Config::default() };
+        let downloader = AssetDownloader::with_auth(auth, config);
+        let depth = std::env::current_dir().unwrap().canonicalize().unwrap().components().count() - 1;
+        let relative = format!("{}{}/root/link/../x.png", "../".repeat(depth), dir.strip_prefix("/").unwrap().display());
+        let validated = downloader.validate_destination_path(&relative);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(resolved, dir.join("outside/x.png"));
+        assert!(matches!(validated, Err(DownloadError::PathRejected(_))));
+    }
+
+    #[test]
+    fn test_build_asset_url() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+        
+        let result = downloader.build_asset_url("1234abcd-1234-1234-1234-1234abcd1234");
+        assert_eq!(result.unwrap(), "https://github.com/user-attachments/assets/1234abcd-1234-1234-1234-1234abcd1234");
+
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let config = Config { host: Some("github.mycorp.com".to_string()), ..Config::default() };
+        let downloader = AssetDownloader::with_auth(auth, config);
+        let result = downloader.build_asset_url("1234abcd-1234-1234-1234-1234abcd1234");
+        assert_eq!(result.unwrap(), "https://github.mycorp.com/user-attachments/assets/1234abcd-1234-1234-1234-1234abcd1234");
+
+        // Numeric IDs go to the releases API; UUIDs still go to user-attachments
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let config = Config { release_repo: Some("YuitoSato/gh-asset".parse().unwrap()), ..Config::default() };
+        let downloader = AssetDownloader::with_auth(auth, config);
+        let url = downloader.build_asset_url("123456789").unwrap();
+        assert_eq!(url, "https://api.github.com/repos/YuitoSato/gh-asset/releases/assets/123456789");
+        assert_eq!(downloader.accept_header(&url), Some("application/octet-stream"));
+        let url = downloader.build_asset_url("1234abcd-1234-1234-1234-1234abcd1234").unwrap();
+        assert_eq!(url, "https://github.com/user-attachments/assets/1234abcd-1234-1234-1234-1234abcd1234");
+        assert_eq!(downloader.accept_header(&url), None);
+
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let config = Config { accept: Some("image/webp".to_string()), ..Config::default() };
+        let downloader = AssetDownloader::with_auth(auth, config);
+        assert_eq!(downloader.accept_header(&url), Some("image/webp"));
+    }
+
+    #[test]
+    fn test_release_lookup() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+        let repo: RepoRef = "YuitoSato/gh-asset".parse().unwrap();
+        assert_eq!(
+            repo.release_by_tag_url("github.com", "release/v1.0+build"),
+            "https://api.github.com/repos/YuitoSato/gh-asset/releases/tags/release%2Fv1.0%2Bbuild"
+        );
+
+        let release: Release = serde_json::from_str(
+            r#"{"tag_name":"v1.0","assets":[{"id":101,"name":"gh-asset-linux.tar.gz"},{"id":102,"name":"gh-asset-macos.tar.gz"}]}"#,
+        )
+        .unwrap();
+        let subject = "release v1.0 of YuitoSato/gh-asset";
+        assert_eq!(downloader.pick_release_asset(&release, subject, "gh-asset-macos.tar.gz").unwrap(), "102");
+        let error = downloader.pick_release_asset(&release, subject, "gh-asset-windows.zip").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "No asset named 'gh-asset-windows.zip' in release v1.0 of YuitoSato/gh-asset (it has gh-asset-linux.tar.gz, gh-asset-macos.tar.gz)"
+        );
+        assert_eq!(error.exit_code(), 2);
+
+        let empty: Release = serde_json::from_str(r#"{"assets":[]}"#).unwrap();
+        let error = downloader.pick_release_asset(&empty, subject, "gh-asset-linux.tar.gz").unwrap_err();
+        assert!(error.to_string().ends_with("(it has none)"));
+    }
+
+    #[tokio::test]
+    async fn test_download_from_base_url() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}/", listener.local_addr().unwrap());
+        let headers = vec![
+            ("X-Proxy-Token".to_string(), "abc".to_string()),
+            ("Authorization".to_string(), "token stolen".to_string()),
+        ];
+        let user_agent = Some("curl/8.0".to_string());
+        let config = Config { quiet: true, headers, user_agent, ..Config::default() };
+        let downloader = AssetDownloader::with_base_url(auth, config, base_url);
+        let asset_id = "1234abcd-1234-1234-1234-1234abcd1234";
+        let server = serve(listener, vec![
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello".to_string(),
+        ]);
+
+        let dir = scratch_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let outcome = downloader.download(asset_id, &dir.to_string_lossy()).await.unwrap().unwrap();
+        let content = std::fs::read(&outcome.path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with(&format!("head /user-attachments/assets/{} ", asset_id)));
+        assert!(requests[1].starts_with(&format!("get /user-attachments/assets/{} ", asset_id)));
+        for request in &requests {
+            assert!(request.contains("x-proxy-token: abc"));
+            assert!(request.contains("user-agent: curl/8.0"));
+            assert!(request.contains("authorization: token fake_token"));
+            assert!(!request.contains("stolen"));
+        }
+        assert_eq!(outcome.path, dir.join(format!("{}.txt", asset_id)));
+        assert_eq!(content, b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_download_through_proxy() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy = format!("http://{}", listener.local_addr().unwrap());
+        let config = Config { quiet: true, proxy: Some(proxy), ..Config::default() };
+        let downloader = AssetDownloader::with_base_url(auth, config, "http://assets.invalid");
+        let server = serve(listener, vec![
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello".to_string(),
+        ]);
+
+        let dir = scratch_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let destination = dir.join("asset.txt");
+        let result = downloader.download("1234abcd-1234-1234-1234-1234abcd1234", &destination.to_string_lossy()).await;
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let requests = server.join().unwrap();
+        assert!(result.is_ok());
+        assert!(requests[0].contains(&format!("user-agent: gh-asset/{}", env!("CARGO_PKG_VERSION"))));
+        // Proxied requests carry the absolute URL
+        assert!(requests[0].starts_with("get http://assets.invalid/user-attachments/assets/1234abcd-1234-1234-1234-1234abcd1234 "));
+    }
+
+    #[tokio::test]
+    async fn test_download_many_skips_head() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let config = Config { quiet: true, concurrency: 1, ..Config::default() };
+        let downloader = AssetDownloader::with_base_url(auth, config, base_url);
+        let asset_ids = vec!["1234abcd-1234-1234-1234-1234abcd1234".to_string(), "abcd1234-5678-9012-3456-789012345678".to_string()];
+        let server = serve(listener, vec![
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello".to_string(),
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{}".to_string(),
+        ]);
+
+        let dir = scratch_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        downloader.download_many(&asset_ids, &dir.to_string_lossy()).await.unwrap();
+        let text = std::fs::read(dir.join(format!("{}.txt", asset_ids[0])));
+        let json = std::fs::read(dir.join(format!("{}.json", asset_ids[1])));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests.iter().all(|request| request.starts_with("get ")));
+        assert_eq!(text.unwrap(), b"hello");
+        assert_eq!(json.unwrap(), b"{}");
+    }
+
+    #[tokio::test]
+    async fn test_download_many_dedupe() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let config = Config { quiet: true, concurrency: 1, dedupe: true, ..Config::default() };
+        let downloader = AssetDownloader::with_base_url(auth, config, base_url);
+        let asset_ids = vec![
+            "1234abcd-1234-1234-1234-1234abcd1234".to_string(),
+            "abcd1234-5678-9012-3456-789012345678".to_string(),
+            "00000000-1111-2222-3333-444444444444".to_string(),
+        ];
+        let response = |body: &str| format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body);
+        let server = serve(listener, vec![response("same"), response("same"), response("different")]);
+
+        let dir = scratch_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        downloader.download_many(&asset_ids, &dir.to_string_lossy()).await.unwrap();
+        server.join().unwrap();
+        let path = |index: usize| dir.join(format!("{}.txt", asset_ids[index]));
+        let contents: Vec<_> = (0..3).map(|index| std::fs::read(path(index)).unwrap()).collect();
+        #[cfg(unix)]
+        let inodes: Vec<_> = {
+            use std::os::unix::fs::MetadataExt;
+            (0..3).map(|index| std::fs::metadata(path(index)).unwrap().ino()).collect()
+        };
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(contents, vec![b"same".to_vec(), b"same".to_vec(), b"different".to_vec()]);
+        assert_eq!(downloader.dedupe_index.lock().unwrap().collapsed, 1);
+        #[cfg(unix)]
+        {
+            assert_eq!(inodes[0], inodes[1]);
+            assert_ne!(inodes[0], inodes[2]);
+        }
+    }
+
+    #[test]
+    fn test_build_asset_url_invalid() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+        
+        let result = downloader.build_asset_url("invalid@id");
+        assert!(result.is_err());
+        
+        let result = downloader.build_asset_url("../../../etc/passwd");
+        assert!(result.is_err());
+
+        let result = downloader.build_asset_url("123456789");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_build_asset_url_without_verifying_id() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let config = Config { no_verify_asset_id: true, ..Config::default() };
+        let downloader = AssetDownloader::with_auth(auth, config);
+
+        let result = downloader.build_asset_url("a1b2c3d4e5_new.format");
+        assert_eq!(result.unwrap(), "https://github.com/user-attachments/assets/a1b2c3d4e5_new.format");
+
+        for unsafe_id in ["", "..", "../../../etc/passwd", "a/b", "a\\b", "id with spaces", "id\n", "a?b", "a#b", "%2e%2e"] {
+            assert!(downloader.build_asset_url(unsafe_id).is_err(), "{:?}", unsafe_id);
+        }
+    }
+
+    #[test]
+    fn test_rename_template() {
+        let template: RenameTemplate = "{date}-{id}{ext}".parse().unwrap();
+        assert_eq!(template.render("abc", ".png", None, "2024-06-01"), "2024-06-01-abc.png");
+        let template: RenameTemplate = "{original}".parse().unwrap();
+        assert_eq!(template.render("abc", ".png", Some("diagram.png"), "2024-06-01"), "diagram.png");
+        assert_eq!(template.render("abc", ".png", None, "2024-06-01"), "abc.png");
+
+        for invalid in ["{id}/{ext}", "..\\{id}", "{name}{ext}", "{id", "", ".."] {
+            assert!(invalid.parse::<RenameTemplate>().is_err(), "{}", invalid);
+        }
+
+        // Only directory destinations are renamed
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let config = Config { rename: Some("shot-{id}{ext}".parse().unwrap()), ..Config::default() };
+        let downloader = AssetDownloader::with_auth(auth, config);
+        let detected = downloader.resolve_extension_from_headers(None, None, Some("image/png")).chosen;
+        assert_eq!(downloader.resolve_final_path(Path::new("src"), "abc", Some(&detected)), Path::new("src/shot-abc.png"));
+        assert_eq!(downloader.resolve_final_path(Path::new("out.bin"), "abc", Some(&detected)), Path::new("out.bin"));
+    }
+
+    #[test]
+    fn test_parse_repo_ref() {
+        let repo: RepoRef = "YuitoSato/gh-asset".parse().unwrap();
+        assert_eq!(repo, RepoRef { owner: "YuitoSato".to_string(), repo: "gh-asset".to_string() });
+        assert_eq!(repo.to_string(), "YuitoSato/gh-asset");
+
+        for invalid in ["gh-asset", "YuitoSato/", "/gh-asset", "a/b/c", "YuitoSato/gh-asset#1"] {
+            assert!(invalid.parse::<RepoRef>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_get_extension_from_mime_type() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+        
+        assert_eq!(downloader.get_extension_from_mime_type("image/png"), ".png");
+        assert_eq!(downloader.get_extension_from_mime_type("image/jpeg"), ".jpg");
+        assert_eq!(downloader.get_extension_from_mime_type("image/gif"), ".gif");
+        assert_eq!(downloader.get_extension_from_mime_type("application/pdf"), ".pdf");
+        assert_eq!(downloader.get_extension_from_mime_type("unknown/type"), ".bin");
+
+        assert_eq!(downloader.get_extension_from_mime_type("image/heic"), ".heic");
+        assert_eq!(downloader.get_extension_from_mime_type("application/x-7z-compressed"), ".7z");
+        assert_eq!(
+            downloader.get_extension_from_mime_type("application/vnd.openxmlformats-officedocument.wordprocessingml.document"),
+            ".docx"
+        );
+        assert_eq!(downloader.get_extension_from_mime_type("video/webm"), ".webm");
+        assert_eq!(downloader.get_extension_from_mime_type("text/markdown"), ".md");
+
+        // Unlisted media types fall back to their subtype
+        assert_eq!(downloader.get_extension_from_mime_type("image/jxl"), ".jxl");
+        assert_eq!(downloader.get_extension_from_mime_type("video/x-flv"), ".flv");
+        assert_eq!(downloader.get_extension_from_mime_type("image/vnd.adobe.photoshop"), ".bin");
+        assert_eq!(downloader.get_extension_from_mime_type("application/x-unknown"), ".bin");
+    }
+
+    #[test]
+    fn test_mime_types_round_trip() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+
+        assert_eq!(downloader.get_mime_type_from_extension("jpeg"), Some("image/jpeg"));
+        assert_eq!(downloader.get_mime_type_from_extension("xlsx"), Some("application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"));
+        assert_eq!(downloader.get_mime_type_from_extension("exe"), None);
+
+        // Every extension's preferred MIME type maps back to that same extension or an alias of it
+        for (_, extension) in MIME_TYPES {
+            let mime_type = downloader.get_mime_type_from_extension(extension).unwrap();
+            let back = downloader.get_extension_from_mime_type(mime_type);
+            assert_eq!(downloader.get_mime_type_from_extension(back.trim_start_matches('.')), Some(mime_type), "{}", extension);
+        }
+    }
+
+    #[test]
+    fn test_extract_filename_from_disposition() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+        
+        let result = downloader.extract_filename_from_disposition("attachment; filename=\"test.png\"");
+        assert_eq!(result, Some("test.png".to_string()));
+        
+        let result = downloader.extract_filename_from_disposition("attachment; filename=test.jpg");
+        assert_eq!(result, Some("test.jpg".to_string()));
+        
+        let result = downloader.extract_filename_from_disposition("inline");
+        assert_eq!(result, None);
+    }
+
+    #[test]
+    fn test_extract_filename_from_disposition_encoded() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+        let filename = |disposition: &str| downloader.extract_filename_from_disposition(disposition);
+
+        assert_eq!(
+            filename("attachment; filename*=UTF-8''%E8%A8%AD%E8%A8%88%E5%9B%B3.png"),
+            Some("設計図.png".to_string())
+        );
+        assert_eq!(
+            filename("attachment; filename*=utf-8'ja'%E3%82%B9%E3%82%AF%E3%83%AA%E3%83%BC%E3%83%B3%E3%82%B7%E3%83%A7%E3%83%83%E3%83%88%202024.png"),
+            Some("スクリーンショット 2024.png".to_string())
+        );
+        assert_eq!(filename("attachment; filename*=ISO-8859-1'en'%A3%20rates.txt"), Some("£ rates.txt".to_string()));
+
+        // The encoded form wins over the plain one, in either order
+        assert_eq!(
+            filename("attachment; filename=\"my_file.png\"; filename*=UTF-8''my%20file.png"),
+            Some("my file.png".to_string())
+        );
+        assert_eq!(
+            filename("attachment; FILENAME*=UTF-8''my%20file.png; filename=\"my_file.png\""),
+            Some("my file.png".to_string())
+        );
+
+        // Undecodable values fall back to the plain filename
+        assert_eq!(
+            filename("attachment; filename=\"fallback.png\"; filename*=UTF-8''%FF%FE.png"),
+            Some("fallback.png".to_string())
+        );
+        assert_eq!(filename("attachment; filename*=UTF-8''bad%2"), None);
+        assert_eq!(filename("attachment; filename*=KOI8-R''%C6.txt"), None);
+    }
+
+    #[test]
+    fn test_extract_extension_from_url() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+        
+        let result = downloader.extract_extension_from_url("https://github-production-user-asset-6210df.s3.amazonaws.com/111111111/1111111111-1234456-1234-1234-1234-123456789.png?X-Amz-Algorithm=AWS4");
+        assert_eq!(result, Some(".png".to_string()));
+        
+        let result = downloader.extract_extension_from_url("https://example.com/path/file.jpg");
+        assert_eq!(result, Some(".jpg".to_string()));
+        
+        let result = downloader.extract_extension_from_url("https://example.com/path/noextension");
+        assert_eq!(result, None);
+
+        let result = downloader.extract_extension_from_url("https://example.com/releases/gh-asset-linux.tar.gz?X-Amz-Algorithm=AWS4");
+        assert_eq!(result, Some(".tar.gz".to_string()));
+
+        let result = downloader.extract_extension_from_url("https://example.com/path/Screenshot.PNG#frag");
+        assert_eq!(result, Some(".png".to_string()));
+
+        let result = downloader.extract_extension_from_url("https://example.com/path/shell.php");
+        assert_eq!(result, None);
+    }
+
+    #[test]
+    fn test_normalize_extension() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+
+        // Query remnants in a filename are cut off, and what's left must be a known extension
+        assert_eq!(downloader.normalize_extension("photo.png?token=abc"), Some(".png".to_string()));
+        assert_eq!(downloader.normalize_extension("photo.php?token=abc"), None);
+        assert_eq!(downloader.normalize_extension("Report.PDF"), Some(".pdf".to_string()));
+
+        // Double extensions keep both parts, but only the known compounds
+        assert_eq!(downloader.normalize_extension("backup.tar.gz"), Some(".tar.gz".to_string()));
+        assert_eq!(downloader.normalize_extension("backup.TAR.XZ"), Some(".tar.xz".to_string()));
+        assert_eq!(downloader.normalize_extension("notes.txt.gz"), Some(".gz".to_string()));
+        assert_eq!(downloader.normalize_extension("backup.tgz"), Some(".tgz".to_string()));
+
+        assert_eq!(downloader.normalize_extension(".png"), None);
+        assert_eq!(downloader.normalize_extension("noextension"), None);
+
+        // The Content-Disposition filename goes through the same normalization
+        let resolution = downloader.resolve_extension_from_headers(None, Some("attachment; filename=\"dump.tar.gz\""), None);
+        assert_eq!(resolution.chosen.extension, ".tar.gz");
+        assert_eq!(resolution.chosen.mime_type, None);
+        let resolution = downloader.resolve_extension_from_headers(None, Some("attachment; filename=\"x.php?token=abc\""), Some("image/png"));
+        assert_eq!(resolution.chosen.extension, ".png");
+        assert_eq!(resolution.chosen.source, TypeSource::ContentType);
+
+        let config = Config { only_if_type: vec!["gz".to_string()], ..Config::default() };
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, config);
+        assert!(downloader.matches_type_filter(".tar.gz", None));
+    }
+
+    #[test]
+    fn test_split_gh_include_output() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+
+        let output = b"HTTP/2.0 200 OK\r\nContent-Type: image/png\r\nContent-Length: 4\r\n\r\n\x89PNG";
+        let (headers, body) = downloader.split_gh_include_output(output).unwrap();
+        assert_eq!(headers.get("content-type"), Some(&"image/png".to_string()));
+        assert_eq!(body, b"\x89PNG");
+
+        let output = b"HTTP/1.1 200 OK\nContent-Disposition: attachment; filename=\"a.pdf\"\n\n%PDF";
+        let (headers, body) = downloader.split_gh_include_output(output).unwrap();
+        assert_eq!(headers.get("content-disposition"), Some(&"attachment; filename=\"a.pdf\"".to_string()));
+        assert_eq!(body, b"%PDF");
+
+        assert!(downloader.split_gh_include_output(b"no headers here").is_none());
+    }
+
+    #[test]
+    fn test_matches_type_filter() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let config = Config { only_if_type: vec!["png".to_string(), "video/*".to_string()], ..Config::default() };
+        let downloader = AssetDownloader::with_auth(auth, config);
+
+        assert!(downloader.matches_type_filter(".png", None));
+        assert!(downloader.matches_type_filter(".PNG", Some("image/png")));
+        assert!(downloader.matches_type_filter(".mp4", None));
+        assert!(downloader.matches_type_filter(".bin", Some("video/webm")));
+        assert!(!downloader.matches_type_filter(".jpg", Some("image/jpeg")));
+        assert!(!downloader.matches_type_filter(".bin", None));
+
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+        assert!(downloader.matches_type_filter(".bin", None));
+    }
+
+    #[test]
+    fn test_sniff_mime_type() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+
+        assert_eq!(downloader.sniff_mime_type(b"\x89PNG\r\n\x1a\n\x00\x00"), Some("image/png"));
+        assert_eq!(downloader.sniff_mime_type(b"\xff\xd8\xff\xe0"), Some("image/jpeg"));
+        assert_eq!(downloader.sniff_mime_type(b"GIF89a"), Some("image/gif"));
+        assert_eq!(downloader.sniff_mime_type(b"%PDF-1.7"), Some("application/pdf"));
+        assert_eq!(downloader.sniff_mime_type(b"\x00\x00\x00\x18ftypmp42"), Some("video/mp4"));
+        assert_eq!(downloader.sniff_mime_type(b"\x00\x00\x00\x14ftypqt  "), Some("video/quicktime"));
+        assert_eq!(downloader.sniff_mime_type(b"<!DOCTYPE html>"), None);
+        assert_eq!(downloader.sniff_mime_type(b""), None);
+    }
+
+    #[test]
+    fn test_verify_content_type() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let config = Config { only_if_type: vec!["video/*".to_string()], verify_type: true, ..Config::default() };
+        let downloader = AssetDownloader::with_auth(auth, config);
+
+        assert!(downloader.verify_content_type(b"\x00\x00\x00\x18ftypmp42").is_ok());
+        assert!(downloader.verify_content_type(b"\x89PNG\r\n\x1a\n").is_err());
+        assert!(downloader.verify_content_type(b"<html>").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_download_many_continues_after_failures() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+
+        // Invalid IDs fail before any request is made
+        let asset_ids = vec!["not-an-id".to_string(), "also/not/an/id".to_string()];
+        let err = downloader.download_many(&asset_ids, ".").await.unwrap_err();
+        assert_eq!(err.to_string(), "2 of 2 downloads failed");
+        assert!(matches!(err, DownloadError::BatchFailed { failed: 2, total: 2 }));
+    }
+
+    #[test]
+    fn test_resolve_extension_from_headers() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+
+        let resolution = downloader.resolve_extension_from_headers(
+            Some("https://example.com/path/file.gif?sig=abc"),
+            Some("attachment; filename=\"shot.jpeg\""),
+            Some("image/png"),
+        );
+        assert_eq!(resolution.from_url.as_deref(), Some(".gif"));
+        assert_eq!(resolution.from_disposition.as_deref(), Some(".jpeg"));
+        assert_eq!(resolution.from_content_type.as_deref(), Some(".png"));
+        assert_eq!(resolution.chosen.extension, ".gif");
+        assert_eq!(resolution.chosen.source, TypeSource::Url);
+        assert_eq!(resolution.chosen.considered.len(), 3);
+
+        let resolution = downloader.resolve_extension_from_headers(None, Some("attachment; filename=\"shot.jpeg\""), Some("image/png"));
+        assert_eq!(resolution.chosen.extension, ".jpeg");
+        assert_eq!(resolution.chosen.source, TypeSource::Disposition);
+
+        let resolution = downloader.resolve_extension_from_headers(None, None, Some("Image/PNG; charset=binary"));
+        assert_eq!(resolution.chosen.extension, ".png");
+        assert_eq!(resolution.chosen.mime_type.as_deref(), Some("image/png"));
+        assert_eq!(resolution.chosen.source, TypeSource::ContentType);
+
+        let resolution = downloader.resolve_extension_from_headers(Some("https://example.com/noextension"), None, None);
+        assert_eq!(resolution.from_url, None);
+        assert_eq!(resolution.chosen.extension, ".bin");
+        assert_eq!(resolution.chosen.source, TypeSource::Fallback);
+    }
+
+    #[test]
+    fn test_extract_filename_from_query() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+
+        let signed_url = "https://github-production-user-asset-6210df.s3.amazonaws.com/111111111/1111111111-1234456-1234-1234-1234-123456789.png?X-Amz-Algorithm=AWS4-HMAC-SHA256&X-Amz-Credential=AKIAEXAMPLE%2F20240601%2Fus-east-1%2Fs3%2Faws4_request&X-Amz-Date=20240601T000000Z&X-Amz-Expires=300&X-Amz-Signature=abc123&X-Amz-SignedHeaders=host&response-content-disposition=attachment%3B%20filename%3D%22architecture%20diagram.png%22&response-content-type=image%2Fpng";
+        assert_eq!(downloader.extract_filename_from_query(signed_url), Some("architecture diagram.png".to_string()));
+
+        let unquoted = "https://example.com/a?response-content-disposition=attachment%3Bfilename%3Dreport.pdf";
+        assert_eq!(downloader.extract_filename_from_query(unquoted), Some("report.pdf".to_string()));
+
+        let traversal = "https://example.com/a?response-content-disposition=attachment%3Bfilename%3D..%2F..%2Fevil.sh";
+        assert_eq!(downloader.extract_filename_from_query(traversal), None);
+
+        assert_eq!(downloader.extract_filename_from_query("https://example.com/path/file.jpg"), None);
+    }
+
+    #[test]
+    fn test_resolve_final_path_preserves_query_filename() {
+        let asset_id = "1234abcd-1234-1234-1234-1234abcd1234";
+        let location = "https://example.com/a.png?response-content-disposition=attachment%3Bfilename%3Ddiagram.png";
+
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let config = Config { preserve_query_filename: true, ..Config::default() };
+        let downloader = AssetDownloader::with_auth(auth, config);
+        let resolution = downloader.resolve_extension_from_headers(Some(location), None, None);
+        let final_path = downloader.resolve_final_path(Path::new("."), asset_id, Some(&resolution.chosen));
+        assert_eq!(final_path, Path::new(".").join("diagram.png"));
+
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+        let final_path = downloader.resolve_final_path(Path::new("."), asset_id, Some(&resolution.chosen));
+        assert_eq!(final_path, Path::new(".").join(format!("{}.png", asset_id)));
+    }
+
+    #[test]
+    fn test_resolve_final_path_uses_original_name() {
+        let asset_id = "1234abcd-1234-1234-1234-1234abcd1234";
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let config = Config { use_original_name: true, ..Config::default() };
+        let downloader = AssetDownloader::with_auth(auth, config);
+        let name_for = |disposition: Option<&str>| {
+            let resolution = downloader.resolve_extension_from_headers(None, disposition, Some("image/png"));
+            downloader.resolve_final_path(Path::new("."), asset_id, Some(&resolution.chosen))
+        };
+
+        assert_eq!(
+            name_for(Some(r#"attachment; filename="architecture-diagram.png""#)),
+            Path::new(".").join("architecture-diagram.png")
+        );
+        // Names that could escape the directory fall back to the asset ID
+        assert_eq!(
+            name_for(Some(r#"attachment; filename="../../evil.png""#)),
+            Path::new(".").join(format!("{}.png", asset_id))
+        );
+        assert_eq!(name_for(None), Path::new(".").join(format!("{}.png", asset_id)));
+    }
+
+    fn scratch_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("gh-asset-test-{}", uuid::Uuid::new_v4()))
+    }
+
+    /// Answers one connection per canned response on a background thread and hands back the
+    /// request heads it received.
+    fn serve(listener: std::net::TcpListener, responses: Vec<String>) -> std::thread::JoinHandle<Vec<String>> {
+        use std::io::BufRead;
+
+        std::thread::spawn(move || {
+            responses
+                .into_iter()
+                .map(|response| {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+                    let mut head = String::new();
+                    while reader.read_line(&mut head).unwrap() > 2 {}
+                    stream.write_all(response.as_bytes()).unwrap();
+                    head.to_ascii_lowercase()
+                })
+                .collect()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_get_following_redirects_drops_token_across_hosts() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+
+        // 127.0.0.1 and localhost count as different hosts
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = serve(listener, vec![
+            format!("HTTP/1.1 302 Found\r\nLocation: http://localhost:{}/stored\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", port),
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello".to_string(),
+        ]);
+
+        let url = format!("http://127.0.0.1:{}/asset", port);
+        let (response, chain) = downloader.get_following_redirects(&url, Some(std::time::Duration::from_secs(5)), None, None).await.unwrap();
+        assert_eq!(response.url().as_str(), format!("http://localhost:{}/stored", port));
+        assert_eq!(chain.to_string(), format!("127.0.0.1:{} 302 -> localhost:{} 200", port, port));
+        assert_eq!(response.bytes().await.unwrap().as_ref(), b"hello");
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("get /asset "));
+        assert!(requests[0].contains("authorization: token fake_token"));
+        assert!(!requests[0].contains("application/vnd.github"));
+        assert!(requests[1].starts_with("get /stored "));
+        assert!(!requests[1].contains("authorization"));
+
+        // Another port on the same host is another origin too
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let other = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let other_port = other.local_addr().unwrap().port();
+        let url = format!("http://{}/asset", listener.local_addr().unwrap());
+        let server = serve(listener, vec![format!(
+            "HTTP/1.1 302 Found\r\nLocation: http://127.0.0.1:{}/stored\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            other_port
+        )]);
+        let other_server = serve(other, vec!["HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()]);
+
+        downloader.get_following_redirects(&url, Some(std::time::Duration::from_secs(5)), None, None).await.unwrap();
+        assert!(server.join().unwrap()[0].contains("authorization: token fake_token"));
+        assert!(!other_server.join().unwrap()[0].contains("authorization"));
+    }
+
+    #[tokio::test]
+    async fn test_failed_download_reports_redirect_chain() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let config = Config { quiet: true, ..Config::default() };
+        let downloader = AssetDownloader::with_base_url(auth, config, format!("http://127.0.0.1:{}/", port));
+        let server = serve(listener, vec![
+            format!("HTTP/1.1 302 Found\r\nLocation: http://localhost:{}/signed\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", port),
+            "HTTP/1.1 403 Forbidden\r\nContent-Length: 19\r\nConnection: close\r\n\r\nRequest has expired".to_string(),
+        ]);
+
+        let dir = scratch_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let destination = dir.join("shot.png");
+        let error = downloader
+            .download("1234abcd-1234-1234-1234-1234abcd1234", &destination.to_string_lossy())
+            .await
+            .unwrap_err();
+        std::fs::remove_dir_all(&dir).unwrap();
+        server.join().unwrap();
+
+        let message = error.to_string();
+        assert!(message.contains("Request has expired"));
+        assert!(message.ends_with(&format!("(redirects: 127.0.0.1:{} 302 -> localhost:{} 403)", port, port)));
+    }
+
+    #[tokio::test]
+    async fn test_if_modified_since_skips_unchanged_asset() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}/", listener.local_addr().unwrap());
+        let config = Config { quiet: true, if_modified_since: true, write_metadata_sidecar: true, overwrite: true, ..Config::default() };
+        let downloader = AssetDownloader::with_base_url(auth, config, base_url);
+        let asset_id = "1234abcd-1234-1234-1234-1234abcd1234";
+        let server = serve(listener, vec![
+            "HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello".to_string(),
+            "HTTP/1.1 304 Not Modified\r\nETag: \"v1\"\r\nConnection: close\r\n\r\n".to_string(),
+        ]);
+
+        let dir = scratch_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let destination = dir.join("notes.txt");
+        let first = downloader.download(asset_id, &destination.to_string_lossy()).await.unwrap();
+        let sidecar: serde_json::Value = serde_json::from_slice(&std::fs::read(dir.join("notes.txt.json")).unwrap()).unwrap();
+        let second = downloader.download(asset_id, &destination.to_string_lossy()).await.unwrap();
+        let content = std::fs::read(&destination).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let requests = server.join().unwrap();
+        assert!(first.is_some());
+        assert_eq!(sidecar["etag"], "\"v1\"");
+        assert!(!requests[0].contains("if-none-match"));
+        assert!(requests[1].contains("if-none-match: \"v1\""));
+        assert_eq!(second, None);
+        assert_eq!(content, b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_empty_body_is_an_error() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}/", listener.local_addr().unwrap());
+        let downloader = AssetDownloader::with_base_url(auth, Config { quiet: true, ..Config::default() }, base_url.clone());
+        let asset_id = "1234abcd-1234-1234-1234-1234abcd1234";
+        let empty = "HTTP/1.1 200 OK\r\nContent-Type: image/png\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string();
+        let server = serve(listener, vec![empty.clone(), empty]);
+
+        let dir = scratch_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let destination = dir.join("shot.png");
+        let error = downloader.download(asset_id, &destination.to_string_lossy()).await.unwrap_err();
+        let left_behind = std::fs::read_dir(&dir).unwrap().count();
+
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let config = Config { quiet: true, allow_empty: true, ..Config::default() };
+        let downloader = AssetDownloader::with_base_url(auth, config, base_url);
+        let outcome = downloader.download(asset_id, &destination.to_string_lossy()).await.unwrap().unwrap();
+        let saved = std::fs::metadata(&destination).unwrap().len();
+        std::fs::remove_dir_all(&dir).unwrap();
+        server.join().unwrap();
+
+        assert_eq!(error.to_string(), "Downloaded asset was empty. Pass --allow-empty to save it anyway");
+        assert_eq!(left_behind, 0);
+        assert_eq!(outcome.bytes_written, 0);
+        assert_eq!(saved, 0);
+    }
+
+    #[tokio::test]
+    async fn test_download_into_archive() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}/", listener.local_addr().unwrap());
+        let downloader = AssetDownloader::with_base_url(auth, Config { quiet: true, ..Config::default() }, base_url);
+        let asset_ids = vec!["1234abcd-1234-1234-1234-1234abcd1234".to_string(), "abcd1234-5678-9012-3456-789012345678".to_string()];
+        let server = serve(listener, vec![
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello".to_string(),
+            "HTTP/1.1 200 OK\r\nContent-Type: text/csv\r\nContent-Length: 3\r\nConnection: close\r\n\r\na,b".to_string(),
+        ]);
+
+        let dir = scratch_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let archive = dir.join("bundle.zip");
+        let written = downloader.download_into_archive(&asset_ids, &archive.to_string_lossy()).await.unwrap();
+        let mut zip = zip::ZipArchive::new(File::open(&archive).unwrap()).unwrap();
+        let names: Vec<String> = zip.file_names().map(str::to_string).collect();
+        let mut first = String::new();
+        std::io::Read::read_to_string(&mut zip.by_name("1234abcd-1234-1234-1234-1234abcd1234.txt").unwrap(), &mut first).unwrap();
+        let left: Vec<_> = std::fs::read_dir(&dir).unwrap().map(|e| e.unwrap().file_name()).collect();
+        let unsupported = downloader.download_into_archive(&asset_ids, &dir.join("bundle.rar").to_string_lossy()).await;
+        std::fs::remove_dir_all(&dir).unwrap();
+        server.join().unwrap();
+
+        assert_eq!(written, archive);
+        assert_eq!(names.len(), 2);
+        assert!(names.contains(&"abcd1234-5678-9012-3456-789012345678.csv".to_string()));
+        assert_eq!(first, "hello");
+        // Only the archive is left, no loose files or staging directory
+        assert_eq!(left, vec![std::ffi::OsString::from("bundle.zip")]);
+        assert!(matches!(unsupported, Err(DownloadError::Archive(_))));
+    }
+
+    #[test]
+    fn test_pack_tar_gz() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+        assert!(matches!(downloader.output_archive_kind(Path::new("out.TGZ")), Ok(ArchiveKind::TarGz)));
+        assert!(matches!(downloader.output_archive_kind(Path::new("out.tar")), Ok(ArchiveKind::Tar)));
+
+        let dir = scratch_dir();
+        let source = dir.join("files");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("a.txt"), b"hello").unwrap();
+        let archive = dir.join("out.tar.gz");
+        assert_eq!(downloader.pack_archive(ArchiveKind::TarGz, &source, &archive).unwrap(), 1);
+        let target = dir.join("unpacked");
+        downloader.extract_file(ArchiveKind::TarGz, &archive, &target).unwrap();
+        let content = std::fs::read(target.join("a.txt")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(content, b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_verify() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}/", listener.local_addr().unwrap());
+        let downloader = AssetDownloader::with_base_url(auth, Config::default(), base_url);
+        let asset_id = "1234abcd-1234-1234-1234-1234abcd1234";
+        let server = serve(listener, vec![
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello".to_string(),
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhellO".to_string(),
+            "HTTP/1.1 200 OK\r\nContent-Length: 6\r\nConnection: close\r\n\r\nhello!".to_string(),
+        ]);
+
+        let dir = scratch_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let local = dir.join("hello.txt");
+        std::fs::write(&local, b"hello").unwrap();
+        let matching = downloader.verify(asset_id, &local).await.unwrap();
+        let corrupted = downloader.verify(asset_id, &local).await.unwrap();
+        let resized = downloader.verify(asset_id, &local).await.unwrap();
+        let content = std::fs::read(&local).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        server.join().unwrap();
+
+        let hello_sha256 = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        assert!(matching.matches());
+        assert_eq!(matching.remote_sha256.as_deref(), Some(hello_sha256));
+        assert!(!corrupted.matches());
+        assert_eq!(corrupted.remote_size, 5);
+        assert_ne!(corrupted.remote_sha256.as_deref(), Some(hello_sha256));
+        assert!(!resized.matches());
+        assert_eq!((resized.remote_size, resized.remote_sha256), (6, None));
+        assert_eq!(content, b"hello");
+    }
+
+    #[test]
+    fn test_extract_zip_archive() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+
+        let mut buffer = std::io::Cursor::new(Vec::new());
+        let mut writer = zip::ZipWriter::new(&mut buffer);
+        let options = zip::write::SimpleFileOptions::default();
+        writer.start_file("docs/readme.txt", options).unwrap();
+        writer.write_all(b"hello").unwrap();
+        writer.finish().unwrap();
+        let bytes = buffer.into_inner();
+
+        assert_eq!(downloader.get_archive_kind(Path::new("a.zip"), &bytes), Some(ArchiveKind::Zip));
+        let target = scratch_dir();
+        downloader.extract_archive(ArchiveKind::Zip, std::io::Cursor::new(&bytes), &target).unwrap();
+        assert_eq!(std::fs::read(target.join("docs/readme.txt")).unwrap(), b"hello");
+        std::fs::remove_dir_all(&target).unwrap();
+    }
+
+    #[test]
+    fn test_extract_zip_rejects_path_traversal() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+
+        let mut buffer = std::io::Cursor::new(Vec::new());
+        let mut writer = zip::ZipWriter::new(&mut buffer);
+        writer.start_file("../evil.txt", zip::write::SimpleFileOptions::default()).unwrap();
+        writer.write_all(b"evil").unwrap();
+        writer.finish().unwrap();
+        let bytes = buffer.into_inner();
+
+        let target = scratch_dir();
+        assert!(downloader.extract_archive(ArchiveKind::Zip, std::io::Cursor::new(&bytes), &target).is_err());
+        assert!(!target.parent().unwrap().join("evil.txt").exists());
+        std::fs::remove_dir_all(&target).unwrap();
+    }
+
+    #[test]
+    fn test_extract_tar_gz_archive() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+
+        let encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(5);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, "logs/output.log", &b"hello"[..]).unwrap();
+        let bytes = builder.into_inner().unwrap().finish().unwrap();
+
+        assert_eq!(downloader.get_archive_kind(Path::new("a.tar.gz"), &bytes), Some(ArchiveKind::TarGz));
+        assert_eq!(downloader.get_archive_kind(Path::new("a.png"), b"\x89PNG\r\n\x1a\n"), None);
+        let target = scratch_dir();
+        downloader.extract_archive(ArchiveKind::TarGz, std::io::Cursor::new(&bytes), &target).unwrap();
+        assert_eq!(std::fs::read(target.join("logs/output.log")).unwrap(), b"hello");
+        std::fs::remove_dir_all(&target).unwrap();
+    }
+
+    #[test]
+    fn test_redact_headers() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(reqwest::header::AUTHORIZATION, "token secret".parse().unwrap());
+        headers.insert(reqwest::header::ACCEPT, "*/*".parse().unwrap());
+
+        let redacted = redact_headers(&headers);
+        assert_eq!(redacted["authorization"], "[REDACTED]");
+        assert_eq!(redacted["accept"], "*/*");
+    }
+
+    #[test]
+    fn test_redact_url() {
+        let url = reqwest::Url::parse(
+            "https://objects.example.com/a.png?X-Amz-Credential=AKIA%2F20240501&X-Amz-Signature=abc123&token=secret&size=1",
+        )
+        .unwrap();
+        let redacted = redact_url(&url);
+        assert!(!redacted.contains("abc123"));
+        assert!(!redacted.contains("secret"));
+        assert!(redacted.contains("X-Amz-Signature=%5BREDACTED%5D"));
+        assert!(redacted.contains("size=1"));
+
+        let plain = reqwest::Url::parse("https://github.com/user-attachments/assets/1234").unwrap();
+        assert_eq!(redact_url(&plain), "https://github.com/user-attachments/assets/1234");
+    }
+
+    #[tokio::test]
+    async fn test_trace_file_records_exchanges() {
+        let path = scratch_dir().with_extension("jsonl");
+        let auth = GitHubAuth { token: "secret_token".to_string() };
+        let config = Config { trace_file: Some(TraceFile::create(&path).unwrap()), ..Config::default() };
+        let downloader = AssetDownloader::with_auth(auth, config);
+
+        let closed_port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let client = reqwest::Client::new();
+        let request = client
+            .get(format!("http://127.0.0.1:{}/asset", closed_port))
+            .header("Authorization", "token secret_token");
+        let error = downloader.send_traced(&client, request).await.unwrap_err();
+        assert!(!downloader.should_retry_timeout(&error, 0));
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(!contents.contains("secret_token"));
+
+        let events: Vec<serde_json::Value> = contents.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["event"], "http_error");
+        assert_eq!(events[0]["method"], "GET");
+        assert_eq!(events[0]["request_headers"]["authorization"], "[REDACTED]");
+        assert_eq!(events[1]["event"], "retry_decision");
+        assert_eq!(events[1]["retry"], false);
+    }
+
+    fn convert_sink_test(body: &[u8], content_type: &str, keep_original: bool) -> (Result<Option<DownloadOutcome>>, Vec<String>) {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let config = Config { quiet: true, convert: "jpg".parse().ok(), keep_original, ..Config::default() };
+        let downloader = AssetDownloader::with_auth(auth, config);
+        let dir = scratch_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = AssetSource {
+            asset_id: "1234abcd-1234-1234-1234-1234abcd1234".to_string(),
+            source_url: "https://github.com/user-attachments/assets/1234abcd-1234-1234-1234-1234abcd1234".to_string(),
+            final_url: None,
+            content_type: Some(content_type.to_string()),
+        };
+        let mut sink = AssetSink::new(&downloader, &dir.join("shot.png"), false, source, Some(body.len() as u64));
+        let result = sink.push(body).and_then(|_| sink.finish());
+        let mut files: Vec<_> = std::fs::read_dir(&dir).unwrap().map(|e| e.unwrap().file_name().to_string_lossy().into_owned()).collect();
+        files.sort();
+        std::fs::remove_dir_all(&dir).unwrap();
+        (result, files)
+    }
+
+    #[test]
+    fn test_convert_image() {
+        let mut png = std::io::Cursor::new(Vec::new());
+        image::RgbaImage::from_pixel(2, 2, image::Rgba([255, 0, 0, 128])).write_to(&mut png, image::ImageFormat::Png).unwrap();
+        let png = png.into_inner();
+
+        let (result, files) = convert_sink_test(&png, "image/png", false);
+        let outcome = result.unwrap().unwrap();
+        assert_eq!(outcome.extension.as_deref(), Some(".jpg"));
+        assert_eq!(outcome.content_type.as_deref(), Some("image/jpeg"));
+        assert_eq!(files, vec!["shot.jpg"]);
+
+        let (result, files) = convert_sink_test(&png, "image/png", true);
+        assert!(result.is_ok());
+        assert_eq!(files, vec!["shot.jpg", "shot.png"]);
+
+        // Anything that isn't a decodable image fails before it is saved
+        let (result, files) = convert_sink_test(b"hello", "text/plain", false);
+        assert!(matches!(result, Err(DownloadError::Convert(message)) if message.contains("not an image")));
+        assert!(files.is_empty());
+        let (result, _) = convert_sink_test(b"\0\0\0\x18ftypheic", "image/heic", false);
+        assert!(matches!(result, Err(DownloadError::Convert(message)) if message.contains("image/heic")));
+
+        assert!("heic".parse::<ConvertFormat>().is_err());
+        assert_eq!(".JPEG".parse::<ConvertFormat>().unwrap().extension(), "jpg");
+    }
+
+    #[test]
+    fn test_unique_names() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config { quiet: true, unique_names: true, ..Config::default() });
+        let dir = scratch_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("shot.txt"), "first").unwrap();
+        std::fs::write(dir.join("shot (1).txt"), "second").unwrap();
+        let source = AssetSource {
+            asset_id: "1234abcd-1234-1234-1234-1234abcd1234".to_string(),
+            source_url: "https://github.com/user-attachments/assets/1234abcd-1234-1234-1234-1234abcd1234".to_string(),
+            final_url: None,
+            content_type: Some("text/plain".to_string()),
+        };
+
+        let mut sink = AssetSink::new(&downloader, &dir.join("shot.txt"), true, source, Some(5));
+        sink.push(b"third").unwrap();
+        let outcome = sink.finish().unwrap().unwrap();
+        let contents: Vec<_> = ["shot.txt", "shot (1).txt", "shot (2).txt"].iter().map(|name| std::fs::read_to_string(dir.join(name)).unwrap()).collect();
+        let count = std::fs::read_dir(&dir).unwrap().count();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(outcome.path, dir.join("shot (2).txt"));
+        assert_eq!(contents, vec!["first", "second", "third"]);
+        assert_eq!(count, 3);
+    }
+
+    #[test]
+    fn test_write_metadata_sidecar() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let repo = "YuitoSato/gh-asset".parse().ok();
+        let config = Config { write_metadata_sidecar: true, repo, ..Config::default() };
+        let downloader = AssetDownloader::with_auth(auth, config);
+
+        let dir = scratch_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let destination = dir.join("image.png");
+        let source = AssetSource {
+            asset_id: "1234abcd-1234-1234-1234-1234abcd1234".to_string(),
+            source_url: "https://github.com/user-attachments/assets/1234abcd-1234-1234-1234-1234abcd1234".to_string(),
+            final_url: Some("https://example.com/image.png".to_string()),
+            content_type: Some("image/png".to_string()),
+        };
+        let mut sink = AssetSink::new(&downloader, &destination, false, source, Some(5));
+        sink.push(b"hello").unwrap();
+        sink.finish().unwrap();
+
+        let sidecar: serde_json::Value = serde_json::from_slice(&std::fs::read(dir.join("image.png.json")).unwrap()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(sidecar["asset_id"], "1234abcd-1234-1234-1234-1234abcd1234");
+        assert_eq!(sidecar["repo"], "YuitoSato/gh-asset");
+        assert_eq!(sidecar["final_url"], "https://example.com/image.png");
+        assert_eq!(sidecar["content_type"], "image/png");
+        assert_eq!(sidecar["size"], 5);
+        assert_eq!(sidecar["sha256"], "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824");
+        assert!(sidecar["downloaded_at"].is_string());
+    }
+
+    #[test]
+    fn test_content_type_override_takes_precedence() {
+        let asset_id = "1234abcd-1234-1234-1234-1234abcd1234";
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let config = Config {
+            content_type_overrides: HashMap::from([(asset_id.to_string(), "image/jpeg".to_string())]),
+            ..Config::default()
+        };
+        let downloader = AssetDownloader::with_auth(auth, config);
+
+        let detected = downloader.get_overridden_type(asset_id).unwrap();
+        assert_eq!(detected.extension, ".jpg");
+        assert_eq!(detected.mime_type.as_deref(), Some("image/jpeg"));
+        assert!(downloader.get_overridden_type("abcd1234-5678-9012-3456-789012345678").is_none());
+
+        let final_path = downloader.resolve_final_path(Path::new("."), asset_id, Some(&detected));
+        assert_eq!(final_path, Path::new(".").join(format!("{}.jpg", asset_id)));
+    }
+
+    #[test]
+    fn test_skip_missing_for_refresh() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let config = Config { replace_existing_only: true, ..Config::default() };
+        let downloader = AssetDownloader::with_auth(auth, config);
+
+        assert!(downloader.skip_missing_for_refresh("id", Path::new("does-not-exist.png")));
+        assert!(!downloader.skip_missing_for_refresh("id", Path::new("Cargo.toml")));
+
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+        assert!(!downloader.skip_missing_for_refresh("id", Path::new("does-not-exist.png")));
+    }
+
+    #[test]
+    fn test_check_html_response() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+        let html = b"<!DOCTYPE html><html><body>Sign in</body></html>";
+
+        // Default: fatal for binary destinations, kept otherwise
+        assert!(downloader.check_html_response(Some("text/html; charset=utf-8"), html, Path::new("a.png")).is_err());
+        assert!(downloader.check_html_response(None, html, Path::new("a.mp4")).is_err());
+        assert!(downloader.check_html_response(Some("text/html"), html, Path::new("page.html")).unwrap());
+        assert!(downloader.check_html_response(Some("image/png"), b"\x89PNG\r\n\x1a\n", Path::new("a.png")).unwrap());
+
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let config = Config { on_html: Some(OnHtml::Skip), ..Config::default() };
+        let downloader = AssetDownloader::with_auth(auth, config);
+        assert!(!downloader.check_html_response(Some("text/html"), html, Path::new("a.png")).unwrap());
+
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let config = Config { on_html: Some(OnHtml::Save), ..Config::default() };
+        let downloader = AssetDownloader::with_auth(auth, config);
+        assert!(downloader.check_html_response(Some("text/html"), html, Path::new("a.png")).unwrap());
+    }
+
+    #[test]
+    fn test_throttle() {
+        let mut throttle = Throttle::new(1000);
+        let pause = throttle.pause(500).unwrap();
+        assert!(pause > std::time::Duration::from_millis(400) && pause <= std::time::Duration::from_millis(500));
+        // The allowance accumulates, so the second chunk is due a second in
+        let pause = throttle.pause(500).unwrap();
+        assert!(pause > std::time::Duration::from_millis(900));
+
+        let mut throttle = Throttle::new(u64::MAX);
+        assert_eq!(throttle.pause(500), None);
+    }
+
+    #[test]
+    fn test_check_extension_matches() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+        let jpeg = downloader.resolve_extension_from_headers(None, None, Some("image/jpeg")).chosen;
+
+        // Mismatches only warn by default; aliases and unknown extensions are fine
+        assert!(downloader.check_extension_matches(Path::new("photo.png"), &jpeg).is_ok());
+        assert!(downloader.check_extension_matches(Path::new("photo.jpeg"), &jpeg).is_ok());
+        assert!(downloader.check_extension_matches(Path::new("photo.raw"), &jpeg).is_ok());
+
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let config = Config { strict: true, ..Config::default() };
+        let downloader = AssetDownloader::with_auth(auth, config);
+        let error = downloader.check_extension_matches(Path::new("photo.png"), &jpeg).unwrap_err();
+        assert!(error.to_string().contains("photo.png has a .png extension, but the asset is image/jpeg"));
+        assert!(downloader.check_extension_matches(Path::new("photo.JPG"), &jpeg).is_ok());
+        assert!(downloader.check_extension_matches(Path::new("photo"), &jpeg).is_ok());
+
+        let unknown = downloader.resolve_extension_from_headers(None, None, Some("application/octet-stream")).chosen;
+        assert!(downloader.check_extension_matches(Path::new("photo.png"), &unknown).is_ok());
+    }
+
+    #[test]
+    fn test_hashed_path() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let config = Config { hash_name: true, hash_name_length: 12, ..Config::default() };
+        let downloader = AssetDownloader::with_auth(auth, config);
+
+        let result = downloader.hashed_path(
+            Path::new("downloads/1234abcd-1234-1234-1234-1234abcd1234.png"),
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+        );
+        assert_eq!(result, PathBuf::from("downloads/2cf24dba5fb0.png"));
+    }
+
+    #[test]
+    fn test_asset_sink_streams_chunks() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let config = Config { hash_name: true, hash_name_length: 12, quiet: true, ..Config::default() };
+        let downloader = AssetDownloader::with_auth(auth, config);
+
+        let dir = scratch_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = AssetSource {
+            asset_id: "1234abcd-1234-1234-1234-1234abcd1234".to_string(),
+            source_url: "https://github.com/user-attachments/assets/1234abcd-1234-1234-1234-1234abcd1234".to_string(),
+            final_url: None,
+            content_type: None,
+        };
+        let mut sink = AssetSink::new(&downloader, &dir.join("1234abcd-1234-1234-1234-1234abcd1234.txt"), true, source, None);
+        assert!(sink.push(b"he").unwrap());
+        assert!(sink.push(b"llo").unwrap());
+        sink.finish().unwrap();
+
+        let entries: Vec<_> = std::fs::read_dir(&dir).unwrap().map(|e| e.unwrap().file_name()).collect();
+        let content = std::fs::read(dir.join("2cf24dba5fb0.txt")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(entries, vec![std::ffi::OsString::from("2cf24dba5fb0.txt")]);
+        assert_eq!(content, b"hello");
+    }
+
+    #[test]
+    fn test_asset_sink_checks_sha256() {
+        let hello = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        let dir = scratch_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = || AssetSource {
+            asset_id: "1234abcd-1234-1234-1234-1234abcd1234".to_string(),
+            source_url: "https://github.com/user-attachments/assets/1234abcd-1234-1234-1234-1234abcd1234".to_string(),
+            final_url: None,
+            content_type: None,
+        };
+        let download = |expected: &str, name: &str| {
+            let auth = GitHubAuth { token: "fake_token".to_string() };
+            let config = Config { sha256: Some(expected.to_string()), quiet: true, ..Config::default() };
+            let downloader = AssetDownloader::with_auth(auth, config);
+            let mut sink = AssetSink::new(&downloader, &dir.join(name), false, source(), None);
+            sink.push(b"hello").unwrap();
+            sink.finish()
+        };
+
+        let matched = download(hello, "good.txt");
+        let mismatched = download(&hello.replace('2', "3"), "bad.txt");
+        let good_exists = dir.join("good.txt").exists();
+        let bad_exists = dir.join("bad.txt").exists();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(matched.is_ok());
+        assert!(good_exists);
+        assert!(matches!(mismatched, Err(DownloadError::ChecksumMismatch { actual, .. }) if actual == hello));
+        assert!(!bad_exists);
+    }
+
+    #[test]
+    fn test_asset_sink_replaces_destination_only_when_complete() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config { overwrite: true, quiet: true, ..Config::default() });
+        let dir = scratch_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let destination = dir.join("asset.txt");
+        std::fs::write(&destination, b"old").unwrap();
+        let source = || AssetSource {
+            asset_id: "1234abcd-1234-1234-1234-1234abcd1234".to_string(),
+            source_url: "https://github.com/user-attachments/assets/1234abcd-1234-1234-1234-1234abcd1234".to_string(),
+            final_url: None,
+            content_type: None,
+        };
+
+        // An interrupted download leaves the existing file as it was
+        let mut sink = AssetSink::new(&downloader, &destination, false, source(), None);
+        sink.push(&[b'x'; SNIFF_LEN]).unwrap();
+        sink.abandon();
+        let after_abandon = std::fs::read(&destination).unwrap();
+
+        let mut sink = AssetSink::new(&downloader, &destination, false, source(), None);
+        sink.push(b"new").unwrap();
+        sink.finish().unwrap();
+
+        let entries: Vec<_> = std::fs::read_dir(&dir).unwrap().map(|e| e.unwrap().file_name()).collect();
+        let content = std::fs::read(&destination).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(after_abandon, b"old");
+        assert_eq!(entries, vec![std::ffi::OsString::from("asset.txt")]);
+        assert_eq!(content, b"new");
+    }
+
+    #[test]
+    fn test_timeout_for_attempt() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+        assert_eq!(downloader.timeout_for_attempt(0), None);
+        assert_eq!(downloader.head_timeout().as_secs(), 30);
+
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config { timeout: Some(300), ..Config::default() });
+        assert_eq!(downloader.timeout_for_attempt(0), Some(std::time::Duration::from_secs(300)));
+        assert_eq!(downloader.timeout_for_attempt(1), Some(std::time::Duration::from_secs(600)));
+        assert_eq!(downloader.timeout_for_attempt(2), Some(std::time::Duration::from_secs(900)));
+        assert_eq!(downloader.head_timeout().as_secs(), 30);
+
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config { timeout: Some(10), ..Config::default() });
+        assert_eq!(downloader.timeout_for_attempt(1), Some(std::time::Duration::from_secs(20)));
+        assert_eq!(downloader.head_timeout().as_secs(), 10);
+
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config { timeout: Some(0), ..Config::default() });
+        assert_eq!(downloader.timeout_for_attempt(0), None);
+        assert_eq!(downloader.head_timeout().as_secs(), 30);
+    }
+
+    #[tokio::test]
+    async fn test_stall_timeout_spares_steady_downloads() {
+        // Announces a 5-byte body and sends `body` a byte at a time, pausing `gap` before each byte
+        fn trickle(listener: std::net::TcpListener, body: &'static [u8], gap: std::time::Duration) -> std::thread::JoinHandle<()> {
+            use std::io::BufRead;
+
+            std::thread::spawn(move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+                let mut head = String::new();
+                while reader.read_line(&mut head).unwrap() > 2 {}
+                let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: image/png\r\nContent-Length: 5\r\nConnection: close\r\n\r\n");
+                for byte in body {
+                    std::thread::sleep(gap);
+                    if stream.write_all(&[*byte]).is_err() {
+                        break;
+                    }
+                }
+            })
+        }
+        let asset_id = "1234abcd-1234-1234-1234-1234abcd1234";
+        let config = || Config { quiet: true, stall_timeout: Some(1), ..Config::default() };
+        let dir = scratch_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // Takes longer than the stall window in total, but never goes quiet for that long
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}/", listener.local_addr().unwrap());
+        let server = trickle(listener, b"hello", std::time::Duration::from_millis(300));
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_base_url(auth, config(), base_url);
+        let steady = downloader.download(asset_id, &dir.join("steady.png").to_string_lossy()).await;
+        server.join().unwrap();
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}/", listener.local_addr().unwrap());
+        let server = trickle(listener, b"h", std::time::Duration::from_millis(2500));
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_base_url(auth, config(), base_url);
+        let stalled = downloader.download(asset_id, &dir.join("stalled.png").to_string_lossy()).await;
+        let saved = std::fs::read(dir.join("steady.png")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        server.join().unwrap();
+
+        assert_eq!(steady.unwrap().unwrap().bytes_written, 5);
+        assert_eq!(saved, b"hello");
+        assert!(matches!(stalled, Err(DownloadError::Network { error, .. }) if error.is_timeout()));
+    }
+
+    #[tokio::test]
+    async fn test_should_retry_timeout_only_for_timeouts() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let config = Config { timeout_retries: 1, ..Config::default() };
+        let downloader = AssetDownloader::with_auth(auth, config);
+
+        // A listener that is dropped refuses connections: a network error, not a timeout
+        let closed_port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let connect_error = reqwest::Client::new()
+            .get(format!("http://127.0.0.1:{}", closed_port))
+            .send()
+            .await
+            .unwrap_err();
+        assert!(!downloader.should_retry_timeout(&connect_error, 0));
+
+        // A listener that accepts but never answers makes the request time out
+        let silent = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let timeout_error = reqwest::Client::new()
+            .get(format!("http://{}", silent.local_addr().unwrap()))
+            .timeout(std::time::Duration::from_millis(50))
+            .send()
+            .await
+            .unwrap_err();
+        assert!(timeout_error.is_timeout());
+        assert!(downloader.should_retry_timeout(&timeout_error, 0));
+        assert!(!downloader.should_retry_timeout(&timeout_error, 1));
+    }
+
+    #[test]
+    fn test_asset_sink_sniffs_bin_extension() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config { quiet: true, ..Config::default() });
+        let png = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR";
+        let source = || AssetSource {
+            asset_id: "1234abcd-1234-1234-1234-1234abcd1234".to_string(),
+            source_url: "https://github.com/user-attachments/assets/1234abcd-1234-1234-1234-1234abcd1234".to_string(),
+            final_url: None,
+            content_type: Some("application/octet-stream".to_string()),
+        };
+
+        let dir = scratch_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let generated = dir.join("1234abcd-1234-1234-1234-1234abcd1234.bin");
+        let mut sink = AssetSink::new(&downloader, &generated, true, source(), None);
+        sink.push(png).unwrap();
+        let sniffed = sink.finish().unwrap();
+
+        // A name the user chose is kept as given
+        let chosen = dir.join("chosen.bin");
+        let mut sink = AssetSink::new(&downloader, &chosen, false, source(), None);
+        sink.push(png).unwrap();
+        let kept = sink.finish().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let sniffed = sniffed.unwrap();
+        assert_eq!(sniffed.path, dir.join("1234abcd-1234-1234-1234-1234abcd1234.png"));
+        assert_eq!(sniffed.bytes_written, png.len() as u64);
+        assert_eq!(sniffed.content_type.as_deref(), Some("application/octet-stream"));
+        assert_eq!(sniffed.extension.as_deref(), Some(".png"));
+        assert_eq!(kept.map(|outcome| outcome.path), Some(chosen));
+    }
+
+    #[test]
+    fn test_check_existing() {
+        let dir = scratch_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let existing = dir.join("image.png");
+        std::fs::write(&existing, b"edited").unwrap();
+
+        let check = |config: Config, path: &Path| {
+            let auth = GitHubAuth { token: "fake_token".to_string() };
+            AssetDownloader::with_auth(auth, config).check_existing(path)
+        };
+        let missing = check(Config::default(), &dir.join("new.png")).unwrap();
+        let default = check(Config::default(), &existing);
+        let overwrite = check(Config { overwrite: true, ..Config::default() }, &existing).unwrap();
+        let no_clobber = check(Config { no_clobber: true, ..Config::default() }, &existing).unwrap();
+        let refresh = check(Config { replace_existing_only: true, ..Config::default() }, &existing).unwrap();
+        // Without a terminal to ask on, --prompt-on-overwrite must not replace the file
+        let unprompted = (!std::io::stdout().is_terminal())
+            .then(|| check(Config { prompt_on_overwrite: true, ..Config::default() }, &existing));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(missing);
+        assert!(default.unwrap_err().to_string().contains("already exists"));
+        assert!(overwrite);
+        assert!(!no_clobber);
+        assert!(refresh);
+        if let Some(unprompted) = unprompted {
+            assert!(matches!(unprompted, Err(DownloadError::AlreadyExists(path)) if path == existing));
+        }
+    }
+
+    #[test]
+    fn test_token_from_env() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| vars.iter().find(|(n, _)| *n == name).map(|(_, v)| v.to_string())
+        };
+
+        assert_eq!(GitHubAuth::token_from_env(env(&[("GITHUB_TOKEN", "ci")])), Some("ci".to_string()));
+        assert_eq!(
+            GitHubAuth::token_from_env(env(&[("GITHUB_TOKEN", "ci"), ("GH_ASSET_TOKEN", "mine")])),
+            Some("mine".to_string())
+        );
+        assert_eq!(
+            GitHubAuth::token_from_env(env(&[("GITHUB_TOKEN", "ci"), ("GH_TOKEN", "gh")])),
+            Some("gh".to_string())
+        );
+        // An empty variable doesn't count as set
+        assert_eq!(GitHubAuth::token_from_env(env(&[("GH_ASSET_TOKEN", " "), ("GITHUB_TOKEN", "ci")])), Some("ci".to_string()));
+        assert_eq!(GitHubAuth::token_from_env(env(&[])), None);
+    }
+
+    #[test]
+    fn test_parse_oauth_scopes() {
+        assert_eq!(parse_oauth_scopes("repo, read:org,gist"), vec!["repo", "read:org", "gist"]);
+        assert!(parse_oauth_scopes("").is_empty());
+    }
+
+    #[test]
+    fn test_backoff_delay() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+
+        for (retry, base_ms) in [(0, 500), (1, 1000), (2, 2000), (10, 30_000), (40, 30_000)] {
+            let delay = downloader.backoff_delay(retry).as_millis() as u64;
+            assert!((base_ms..=base_ms * 3 / 2).contains(&delay), "retry {}: {}ms", retry, delay);
+        }
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+        let secs = std::time::Duration::from_secs;
+
+        assert_eq!(downloader.parse_retry_after("30"), Some(secs(30)));
+        assert_eq!(downloader.parse_retry_after("86400"), Some(secs(MAX_RETRY_AFTER_SECS)));
+        assert_eq!(downloader.parse_retry_after("Sun, 06 Nov 1994 08:49:37 GMT"), Some(secs(0)));
+        let soon = (chrono::Utc::now() + chrono::Duration::seconds(60)).to_rfc2822().replace("+0000", "GMT");
+        let delay = downloader.parse_retry_after(&soon).unwrap();
+        assert!(delay > secs(55) && delay <= secs(60), "{:?}", delay);
+        assert_eq!(downloader.parse_retry_after("soon"), None);
+    }
+
+    #[tokio::test]
+    async fn test_download_retries_after_429() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}/", listener.local_addr().unwrap());
+        let config = Config { quiet: true, retries: 1, ..Config::default() };
+        let downloader = AssetDownloader::with_base_url(auth, config, base_url);
+        let server = serve(listener, vec![
+            "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 1\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello".to_string(),
+        ]);
+
+        let dir = scratch_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let destination = dir.join("notes.txt");
+        let started = std::time::Instant::now();
+        downloader.download("1234abcd-1234-1234-1234-1234abcd1234", &destination.to_string_lossy()).await.unwrap();
+        let elapsed = started.elapsed();
+        let content = std::fs::read(&destination).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(server.join().unwrap().len(), 2);
+        // The backoff alone would have waited at most 750ms
+        assert!(elapsed >= std::time::Duration::from_secs(1), "{:?}", elapsed);
+        assert_eq!(content, b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_download_dry_run_writes_nothing() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let config = Config { dry_run: true, create_dirs: true, ..Config::default() };
+        let downloader = AssetDownloader::with_auth(auth, config);
+        let destination = scratch_dir().join("image.png");
+
+        let path = downloader
+            .download("1234abcd-1234-1234-1234-1234abcd1234", &destination.to_string_lossy())
+            .await
+            .unwrap();
+        assert_eq!(path.map(|outcome| outcome.path), Some(destination.clone()));
+        assert!(!destination.parent().unwrap().exists());
+    }
+
+    #[test]
+    fn test_download_error_to_json() {
+        let error = DownloadError::HttpStatus { status: 404, message: "octo/repo#7 not found".to_string() };
+        assert_eq!(
+            error.to_json(),
+            serde_json::json!({ "error": "http_status", "message": "octo/repo#7 not found", "status": 404 })
+        );
+
+        let error = DownloadError::PathRejected("Invalid filename".to_string());
+        assert_eq!(error.to_json(), serde_json::json!({ "error": "path_rejected", "message": "Invalid filename" }));
+    }
+
+    #[test]
+    fn test_check_writable() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+        let dir = scratch_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("file.txt"), b"").unwrap();
+
+        let in_dir = downloader.check_writable(&dir);
+        let missing_parents = downloader.check_writable(&dir.join("a/b/image.png"));
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let creating = AssetDownloader::with_auth(auth, Config { create_dirs: true, ..Config::default() });
+        let created_parents = creating.check_writable(&dir.join("a/b/image.png"));
+        let under_file = creating.check_writable(&dir.join("file.txt/image.png"));
+        let entries = std::fs::read_dir(&dir).unwrap().count();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(in_dir.is_ok());
+        assert!(matches!(missing_parents, Err(DownloadError::PathRejected(message)) if message.ends_with("pass --create-dirs")));
+        assert!(created_parents.is_ok());
+        assert!(matches!(under_file, Err(DownloadError::PathRejected(_))));
+        // The probe file is cleaned up
+        assert_eq!(entries, 1);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_api_reports_missing_issue() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+        let issue: IssueRef = "octo/repo#7".parse().unwrap();
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/repos/octo/repo/issues/7", listener.local_addr().unwrap());
+        let server = serve(listener, vec![
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+        ]);
+
+        let error = downloader.fetch_api::<serde_json::Value>(&issue.to_string(), &url).await.unwrap_err();
+        server.join().unwrap();
+        assert!(matches!(error, DownloadError::HttpStatus { status: 404, .. }));
+        assert_eq!(error.to_string(), "octo/repo#7 not found, or the token has no access to it");
+    }
+
+    #[tokio::test]
+    async fn test_download_with_reqwest_retries_server_errors_only() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let config = Config { retries: 3, quiet: true, ..Config::default() };
+        let downloader = AssetDownloader::with_auth(auth, config);
+        let asset_id = "1234abcd-1234-1234-1234-1234abcd1234";
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/asset", listener.local_addr().unwrap());
+        let server = serve(listener, vec![
+            "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello".to_string(),
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+        ]);
+
+        let dir = scratch_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let destination = dir.join("asset.txt");
+        downloader.download_with_reqwest(asset_id, &url, &destination, false, false).await.unwrap();
+        let content = std::fs::read(&destination).unwrap();
+
+        let error = downloader.download_with_reqwest(asset_id, &url, &destination, false, false).await.unwrap_err();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(content, b"hello");
+        assert_eq!(error.to_string(), "HTTP request failed with status: 404 Not Found - Not Found (after 1 attempt)");
+        assert!(matches!(error, DownloadError::HttpStatus { status: 404, .. }));
+        assert_eq!(server.join().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_download_with_reqwest_reports_error_body() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let config = Config { quiet: true, ..Config::default() };
+        let downloader = AssetDownloader::with_auth(auth, config);
+        let asset_id = "1234abcd-1234-1234-1234-1234abcd1234";
+
+        let body = "<?xml version=\"1.0\"?>\n<Error>\n  <Code>AccessDenied</Code>\n  <Message>Request has expired</Message>\n</Error>";
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/asset", listener.local_addr().unwrap());
+        let server = serve(listener, vec![format!(
+            "HTTP/1.1 403 Forbidden\r\nContent-Type: application/xml\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )]);
+
+        let destination = scratch_dir().join("asset.txt");
+        let error = downloader.download_with_reqwest(asset_id, &url, &destination, false, false).await.unwrap_err();
+        server.join().unwrap();
+        assert!(matches!(error, DownloadError::HttpStatus { status: 403, .. }));
+        assert_eq!(
+            error.to_string(),
+            "HTTP request failed with status: 403 Forbidden - Forbidden (after 1 attempt): <?xml version=\"1.0\"?> <Error> <Code>AccessDenied</Code> <Message>Request has expired</Message> </Error>"
+        );
+
+        let long = "x".repeat(1000);
+        assert_eq!(downloader.error_snippet(&long).unwrap(), format!("{}...", "x".repeat(300)));
+        assert_eq!(downloader.error_snippet(" \n "), None);
+    }
+
+    #[tokio::test]
+    async fn test_download_with_reqwest_resumes_partial_file() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let config = Config { resume: true, overwrite: true, quiet: true, ..Config::default() };
+        let downloader = AssetDownloader::with_auth(auth, config);
+        let asset_id = "1234abcd-1234-1234-1234-1234abcd1234";
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/asset", listener.local_addr().unwrap());
+        let server = serve(listener, vec![
+            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 3-4/5\r\nContent-Length: 2\r\nConnection: close\r\n\r\nlo".to_string(),
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello".to_string(),
+        ]);
+
+        let dir = scratch_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let destination = dir.join("asset.txt");
+        let part = dir.join(".asset.txt.part");
+
+        std::fs::write(&part, b"hel").unwrap();
+        downloader.download_with_reqwest(asset_id, &url, &destination, false, false).await.unwrap();
+        let resumed = std::fs::read(&destination).unwrap();
+
+        // A server that ignores the range sends the whole body, which replaces the partial file
+        std::fs::write(&part, b"xyz").unwrap();
+        downloader.download_with_reqwest(asset_id, &url, &destination, false, false).await.unwrap();
+        let restarted = std::fs::read(&destination).unwrap();
+        let part_left = part.exists();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let requests = server.join().unwrap();
+        assert!(requests.iter().all(|request| request.contains("range: bytes=3-")));
+        assert_eq!(resumed, b"hello");
+        assert_eq!(restarted, b"hello");
+        assert!(!part_left);
+    }
+
+    #[tokio::test]
+    async fn test_download_with_reqwest_resumes_partial_file_longer_than_sniff_prefix() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let config = Config { resume: true, overwrite: true, quiet: true, ..Config::default() };
+        let downloader = AssetDownloader::with_auth(auth, config);
+        let asset_id = "1234abcd-1234-1234-1234-1234abcd1234";
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/asset", listener.local_addr().unwrap());
+        let server = serve(listener, vec![
+            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 1000-1009/1010\r\nContent-Length: 10\r\nConnection: close\r\n\r\nbbbbbbbbbb".to_string(),
+        ]);
+
+        let dir = scratch_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let destination = dir.join("asset.txt");
+        std::fs::write(dir.join(".asset.txt.part"), vec![b'a'; 1000]).unwrap();
+        let outcome = downloader.download_with_reqwest(asset_id, &url, &destination, false, false).await.unwrap().unwrap();
+        let resumed = std::fs::read(&destination).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        server.join().unwrap();
+
+        let mut expected = vec![b'a'; 1000];
+        expected.extend_from_slice(b"bbbbbbbbbb");
+        assert_eq!(resumed, expected);
+        assert_eq!(outcome.bytes_written, 1010);
+    }
+
+    #[tokio::test]
+    async fn test_download_with_reqwest_enforces_max_size() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let config = Config { max_size: Some(4), resume: true, quiet: true, ..Config::default() };
+        let downloader = AssetDownloader::with_auth(auth, config);
+        let asset_id = "1234abcd-1234-1234-1234-1234abcd1234";
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/asset", listener.local_addr().unwrap());
+        let server = serve(listener, vec![
+            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 3-9/10\r\nContent-Length: 7\r\nConnection: close\r\n\r\n3456789".to_string(),
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n3\r\n012\r\n7\r\n3456789\r\n0\r\n\r\n".to_string(),
+        ]);
+
+        let dir = scratch_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let destination = dir.join("asset.txt");
+        let part = dir.join(".asset.txt.part");
+
+        // Announced by Content-Length, counting the resumed bytes; the partial file goes too
+        std::fs::write(&part, b"012").unwrap();
+        let announced = downloader.download_with_reqwest(asset_id, &url, &destination, false, false).await.unwrap_err();
+        let part_left = part.exists();
+
+        // Without a Content-Length, the running count catches it
+        let streamed = downloader.download_with_reqwest(asset_id, &url, &destination, false, false).await.unwrap_err();
+        let saved = destination.exists() || part.exists();
+        std::fs::remove_dir_all(&dir).unwrap();
+        server.join().unwrap();
+
+        assert!(matches!(announced, DownloadError::TooLarge { limit: 4 }));
+        assert!(!part_left);
+        assert!(matches!(streamed, DownloadError::TooLarge { limit: 4 }));
+        assert!(!saved);
+    }
+
+    #[tokio::test]
+    async fn test_failed_type_probe_falls_back_to_get_headers() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let config = Config { quiet: true, ..Config::default() };
+        let downloader = AssetDownloader::with_auth(auth, config);
+        let asset_id = "1234abcd-1234-1234-1234-1234abcd1234";
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/asset", listener.local_addr().unwrap());
+        let server = serve(listener, vec![
+            "HTTP/1.1 405 Method Not Allowed\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+            "HTTP/1.1 200 OK\r\nContent-Type: application/pdf\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello".to_string(),
+        ]);
+
+        let probe = downloader.resolve_extension(&url).await.err().unwrap();
+
+        let dir = scratch_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let fallback = dir.join(format!("{}.bin", asset_id));
+        let saved = downloader.download_with_reqwest(asset_id, &url, &fallback, true, true).await.unwrap();
+        let content = std::fs::read(dir.join(format!("{}.pdf", asset_id))).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        server.join().unwrap();
+
+        assert!(matches!(probe, DownloadError::HttpStatus { status: 405, .. }));
+        let saved = saved.unwrap();
+        assert_eq!(saved.path, dir.join(format!("{}.pdf", asset_id)));
+        assert_eq!(saved.bytes_written, 5);
+        assert_eq!(saved.content_type.as_deref(), Some("application/pdf"));
+        assert_eq!(content, b"hello");
+    }
+
+    #[test]
+    fn test_dropped_sink_removes_temp_file() {
+        let source = || AssetSource {
+            asset_id: "1234abcd-1234-1234-1234-1234abcd1234".to_string(),
+            source_url: "https://github.com/user-attachments/assets/1234abcd-1234-1234-1234-1234abcd1234".to_string(),
+            final_url: None,
+            content_type: None,
+        };
+        let dir = scratch_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let destination = dir.join("asset.bin");
+        let part = dir.join(".asset.bin.part");
+        let body = vec![0u8; SNIFF_LEN * 2];
+
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config { quiet: true, ..Config::default() });
+        let mut sink = AssetSink::new(&downloader, &destination, false, source(), None);
+        sink.push(&body).unwrap();
+        let written = part.exists();
+        drop(sink);
+        let removed = !part.exists();
+
+        // Under --resume the partial file is kept to continue from
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config { quiet: true, resume: true, ..Config::default() });
+        let mut sink = AssetSink::new(&downloader, &destination, false, source(), None);
+        sink.push(&body).unwrap();
+        drop(sink);
+        let kept = part.exists();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(written);
+        assert!(removed);
+        assert!(kept);
+        assert!(!destination.exists());
+    }
+
+    #[test]
+    fn test_transfer_summary() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+
+        let summary = downloader.transfer_summary(25_000_000, std::time::Duration::from_millis(2500));
+        assert_eq!(summary, "Transferred 25.00 MB in 2.5s (10.00 MB/s)");
+        let summary = downloader.transfer_summary(512, std::time::Duration::ZERO);
+        assert_eq!(summary, "Transferred 512 B in 0.0s (512 B/s)");
+    }
+
+    #[test]
+    fn test_content_range_start() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+
+        assert_eq!(downloader.content_range_start("bytes 1024-2047/4096"), Some(1024));
+        assert_eq!(downloader.content_range_start("bytes 0-9/*"), Some(0));
+        assert_eq!(downloader.content_range_start("bytes */4096"), None);
+        assert_eq!(downloader.content_range_start("items 0-9/10"), None);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_trim_windows_filename() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+
+        let result = downloader.trim_windows_filename(Path::new(r"C:\Users\me\image.png. "));
+        assert_eq!(result, Some(PathBuf::from(r"C:\Users\me\image.png")));
+
+        let result = downloader.trim_windows_filename(Path::new(r"downloads\report..."));
+        assert_eq!(result, Some(PathBuf::from(r"downloads\report")));
+
+        assert_eq!(downloader.trim_windows_filename(Path::new(r"downloads\image.png")), None);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_resolve_final_path_trims_trailing_dots_on_windows() {
+        let auth = GitHubAuth { token: "fake_token".to_string() };
+        let downloader = AssetDownloader::with_auth(auth, Config::default());
+
+        let result = downloader.resolve_final_path(Path::new("my-image.png."), "unused", None);
+        assert_eq!(result, PathBuf::from("my-image.png"));
+    }
+}